@@ -0,0 +1,248 @@
+use std::{collections::HashMap, fs::OpenOptions, io::Write, path::Path};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use anyhow::Context;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{sqlite::SqlitePool, types::chrono::Local};
+use zeroize::Zeroize;
+
+use crate::{compile_config::GENERATED_MIN_LENGTH, database::{get_account_by_id, list_accounts, list_audit_excluded, list_expired, list_incomplete_accounts}, encryption::try_decrypt_password, generator::describe_password};
+
+/// Kind of problem found by [`generate_audit_report()`], reports are grouped in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueCategory {
+    Weak,
+    Reused,
+    Stale,
+    Incomplete,
+    /// Encrypted by another master, so the password checks were skipped
+    Unreadable,
+}
+
+impl IssueCategory {
+    fn heading(&self) -> String {
+        match self {
+            IssueCategory::Weak => format!("Weak password (shorter than {} characters or a single character class)", GENERATED_MIN_LENGTH),
+            IssueCategory::Reused => "Reused password".to_string(),
+            IssueCategory::Stale => "Review date passed".to_string(),
+            IssueCategory::Incomplete => "Missing or inconsistent details".to_string(),
+            IssueCategory::Unreadable => "Not readable with this master password".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditIssue {
+    pub account_id: i64,
+    pub account_name: String,
+    pub category: IssueCategory,
+    /// Extra context, never anything derived from the password itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Results of every vault audit, account names and issue categories only
+#[derive(Debug, Serialize)]
+pub struct AuditReport {
+    pub generated_at: String,
+    pub accounts_checked: usize,
+    /// Accounts marked as not real passwords, left out of every check
+    pub accounts_excluded: usize,
+    pub issues: Vec<AuditIssue>,
+}
+
+impl AuditReport {
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        text.push_str("Vault audit report\n");
+        text.push_str(&format!("Generated: {}\n", self.generated_at));
+        text.push_str(&format!("Accounts checked: {}\n", self.accounts_checked));
+        if self.accounts_excluded > 0 {
+            text.push_str(&format!("Accounts excluded from audits: {}\n", self.accounts_excluded));
+        }
+        text.push_str(&format!("Issues found: {}\n", self.issues.len()));
+
+        let mut category = None;
+        for issue in &self.issues {
+            if category != Some(issue.category) {
+                category = Some(issue.category);
+                text.push_str(&format!("\n{}:\n", issue.category.heading()));
+            }
+            text.push_str(&format!("  {} (ID {})", issue.account_name, issue.account_id));
+            if let Some(detail) = &issue.detail {
+                text.push_str(&format!(", {}", detail));
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Runs the weak, reused, stale and incomplete account checks
+///
+/// Every password is decrypted in memory to check it and zeroized right after,
+/// reuse is found by comparing SHA-256 digests that never leave this function.
+/// Accounts excluded from audits are skipped entirely, they don't count towards reuse either
+pub async fn generate_audit_report(pool: &SqlitePool, master_password: &String) -> anyhow::Result<AuditReport> {
+    let excluded = list_audit_excluded(pool).await?;
+    let summaries: Vec<_> = list_accounts(pool)
+        .await?
+        .into_iter()
+        .filter(|summary| !excluded.contains(&summary.id))
+        .collect();
+    let mut issues = Vec::new();
+
+    let mut by_digest: HashMap<[u8; 32], Vec<(i64, String)>> = HashMap::new();
+    for summary in &summaries {
+        let account = get_account_by_id(pool, summary.id).await?;
+        let Ok(mut password) = try_decrypt_password(master_password, &account.password) else {
+            issues.push(AuditIssue {
+                account_id: account.id,
+                account_name: account.name.clone(),
+                category: IssueCategory::Unreadable,
+                detail: None,
+            });
+            continue;
+        };
+
+        let composition = describe_password(&password);
+        let classes = [composition.lowercase, composition.uppercase, composition.digits, composition.symbols]
+            .iter()
+            .filter(|count| **count > 0)
+            .count();
+        if composition.length < GENERATED_MIN_LENGTH || classes < 2 {
+            issues.push(AuditIssue {
+                account_id: account.id,
+                account_name: account.name.clone(),
+                category: IssueCategory::Weak,
+                detail: None,
+            });
+        }
+
+        let digest: [u8; 32] = Sha256::digest(password.as_bytes()).into();
+        password.zeroize();
+        by_digest.entry(digest).or_default().push((account.id, account.name.clone()));
+    }
+
+    for group in by_digest.values().filter(|group| group.len() > 1) {
+        for (id, name) in group {
+            let others: Vec<&str> = group
+                .iter()
+                .filter(|(other_id, _)| other_id != id)
+                .map(|(_, other_name)| other_name.as_str())
+                .collect();
+            issues.push(AuditIssue {
+                account_id: *id,
+                account_name: name.clone(),
+                category: IssueCategory::Reused,
+                detail: Some(format!("same password as {}", others.join(", "))),
+            });
+        }
+    }
+
+    for (summary, review_date, _) in list_expired(pool).await?.into_iter().filter(|(summary, _, _)| !excluded.contains(&summary.id)) {
+        issues.push(AuditIssue {
+            account_id: summary.id,
+            account_name: summary.name.clone(),
+            category: IssueCategory::Stale,
+            detail: Some(format!("review date {}", review_date)),
+        });
+    }
+
+    for summary in list_incomplete_accounts(pool).await? {
+        issues.push(AuditIssue {
+            account_id: summary.id,
+            account_name: summary.name.clone(),
+            category: IssueCategory::Incomplete,
+            detail: None,
+        });
+    }
+
+    issues.sort_by(|a, b| (a.category, &a.account_name).cmp(&(b.category, &b.account_name)));
+
+    Ok(AuditReport {
+        generated_at: Local::now().format("%Y-%m-%d %H:%M").to_string(),
+        accounts_checked: summaries.len(),
+        accounts_excluded: excluded.len(),
+        issues,
+    })
+}
+
+/// Password checks of one account for `list --audit`, nothing derived from the password itself
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct AccountHealth {
+    pub weak: bool,
+    pub reused: bool,
+    /// Encrypted by another master, so it couldn't be checked
+    pub unreadable: bool,
+}
+
+impl AccountHealth {
+    /// Bracketed markers, ie. `[weak] [reused]`, colored for a terminal
+    pub fn markers(&self, color: bool) -> String {
+        let paint = |text: &str, code: &str| if color { format!("\x1b[{}m[{}]\x1b[0m", code, text) } else { format!("[{}]", text) };
+
+        let mut markers = Vec::new();
+        if self.weak {
+            markers.push(paint("weak", "33"));
+        }
+        if self.reused {
+            markers.push(paint("reused", "31"));
+        }
+        if self.unreadable {
+            markers.push(paint("unreadable", "2"));
+        }
+        if markers.is_empty() {
+            markers.push(paint("ok", "32"));
+        }
+        markers.join(" ")
+    }
+}
+
+/// Health of every audited account by ID, from the weak, reused and unreadable checks of [`generate_audit_report()`]
+/// 
+/// Accounts excluded from audits have no entry
+pub async fn account_health(pool: &SqlitePool, master_password: &String) -> anyhow::Result<HashMap<i64, AccountHealth>> {
+    let excluded = list_audit_excluded(pool).await?;
+    let report = generate_audit_report(pool, master_password).await?;
+
+    let mut health: HashMap<i64, AccountHealth> = list_accounts(pool)
+        .await?
+        .into_iter()
+        .filter(|summary| !excluded.contains(&summary.id))
+        .map(|summary| (summary.id, AccountHealth::default()))
+        .collect();
+    for issue in &report.issues {
+        let Some(entry) = health.get_mut(&issue.account_id) else {
+            continue;
+        };
+        match issue.category {
+            IssueCategory::Weak => entry.weak = true,
+            IssueCategory::Reused => entry.reused = true,
+            IssueCategory::Unreadable => entry.unreadable = true,
+            IssueCategory::Stale | IssueCategory::Incomplete => {}
+        }
+    }
+
+    Ok(health)
+}
+
+/// Writes a report to a new file, readable only by the owner on Unix
+pub fn write_audit_report(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
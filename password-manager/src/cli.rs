@@ -0,0 +1,765 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use comfy_table::{presets::UTF8_FULL, Table};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+use zeroize::Zeroize;
+
+use crate::{audit::{account_health, generate_audit_report, write_audit_report, AccountHealth}, compile_config::{DB_PATH, GENERATED_MIN_LENGTH}, database::{add_account, get_account_by_id, get_account_by_name, initialize_memory_db, is_not_found, list_account_overviews, list_accounts, list_accounts_by_use, list_accounts_grouped, load_vault_into_memory, master_exists, scan_for_plaintext, update_account, Account, AccountSummary}, encryption::{encrypt_password, try_decrypt_password}, pass_store::{default_store_path, import_pass_store, ReviewChoice}, share::{export_shared, import_shared, read_identity_file}, generator::{describe_password, generate_passphrase, generate_password, PasswordOptions}, vault_diff::diff_vaults, user_interface::{confirm, obtain_master_credentials, obtain_other_master_password, reauthenticate, print_account_details, print_account_login, print_account_summary_details, print_grouped_accounts, print_separator, review_import_candidate}};
+
+#[derive(Parser)]
+#[command(version, about = "A simple, locally hosted password manager")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the SQLite database, created along with its directory if missing
+    #[arg(long, value_name = "PATH", global = true, default_value = DB_PATH)]
+    pub db_path: PathBuf,
+
+    /// Key file required in addition to the master password.
+    /// Given when the master is first set up, it is needed for every login after
+    #[arg(long, value_name = "PATH", global = true)]
+    pub keyfile: Option<PathBuf>,
+
+    /// Offer to keep the master password in the OS keyring and log in with it.
+    /// Convenient on a trusted machine, but anyone using your OS account can then open the vault
+    #[arg(long, global = true)]
+    pub use_keyring: bool,
+
+    /// Exit with an error on the first wrong master password instead of asking again,
+    /// for scripts and CI
+    #[arg(long, global = true)]
+    pub no_retry: bool,
+
+    /// Language of the interactive menu and login, ie. `de`. Defaults to the one in LC_ALL/LC_MESSAGES/LANG,
+    /// English when there is no translation for it
+    #[arg(long, value_name = "LANG", global = true)]
+    pub lang: Option<String>,
+
+    /// Keep the vault in memory only, nothing is written to disk unless --save-to is given.
+    /// --db-path is ignored
+    #[arg(long, global = true, conflicts_with = "use_keyring")]
+    pub ephemeral: bool,
+
+    /// Start the in-memory vault from a copy of this vault file, which is only read
+    #[arg(long, value_name = "PATH", global = true, requires = "ephemeral")]
+    pub load_from: Option<PathBuf>,
+
+    /// Save the in-memory vault to this file on exit, replacing it if it exists.
+    /// Not saved when interrupted with Ctrl-C
+    #[arg(long, value_name = "PATH", global = true, requires = "ephemeral")]
+    pub save_to: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Check the crypto and database round trips using a temporary in-memory vault
+    Selftest,
+    /// Print a generated password or passphrase, without opening the vault
+    Gen(GenArgs),
+    /// List stored accounts (no master login needed, passwords are never shown)
+    List(ListArgs),
+    /// Show a single account by ID or name
+    Get(GetArgs),
+    /// Show only an account's username and URL (no master login needed, the password is never decrypted)
+    Username(UsernameArgs),
+    /// Find passwords that were encrypted twice by older versions and store them encrypted once
+    Repair,
+    /// Check that no password is stored, or left behind, unencrypted in the database file
+    ScanPlaintext,
+    /// Export one account encrypted to another user's age public key
+    Share(ShareArgs),
+    /// Add an account that was shared with you
+    ImportShared(ImportSharedArgs),
+    /// Import every entry of a `pass` password store, decrypted with your gpg agent
+    ImportPass(ImportPassArgs),
+    /// Write a report of weak, reused, overdue and incomplete accounts, without any passwords
+    Audit(AuditArgs),
+    /// Compare the vault with a backup or another vault file, without showing any passwords
+    Diff(DiffArgs),
+}
+
+#[derive(Args)]
+pub struct GenArgs {
+    /// Number of characters in the password
+    #[arg(short, long, default_value_t = PasswordOptions::default().length)]
+    length: usize,
+    /// Leave out lowercase letters
+    #[arg(long)]
+    no_lowercase: bool,
+    /// Leave out uppercase letters
+    #[arg(long)]
+    no_uppercase: bool,
+    /// Leave out digits
+    #[arg(long)]
+    no_digits: bool,
+    /// Leave out symbols
+    #[arg(long)]
+    no_symbols: bool,
+    /// Characters to never use, ie. symbols a site rejects
+    #[arg(long, value_name = "CHARS", default_value = "")]
+    exclude: String,
+    /// Leave out characters that are easy to mix up (0/O/o, 1/l/I/|), for passwords that are read aloud or typed
+    #[arg(long)]
+    no_ambiguous: bool,
+    /// Allow lengths below the minimum of 8, only for sites that cap the length
+    #[arg(long)]
+    allow_short: bool,
+    /// Generate a passphrase of random words instead
+    #[arg(long)]
+    passphrase: bool,
+    /// Number of words in the passphrase
+    #[arg(long, default_value_t = 6)]
+    words: usize,
+    /// Separator between passphrase words
+    #[arg(long, default_value = "-")]
+    separator: String,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Show a compact table of name, username and URL instead (text format only)
+    #[arg(long, conflicts_with = "format")]
+    table: bool,
+    /// Mark weak, reused and unreadable passwords; asks for the master password to check them
+    #[arg(long, conflicts_with = "table")]
+    audit: bool,
+    /// List the accounts retrieved most often from the menu first, with their count
+    #[arg(long, conflicts_with_all = ["table", "audit"])]
+    most_used: bool,
+}
+
+/// JSON shape for `list --most-used`
+#[derive(Serialize)]
+struct UsedSummary<'a> {
+    #[serde(flatten)]
+    summary: &'a AccountSummary,
+    access_count: i64,
+}
+
+/// JSON shape for `list --audit`
+#[derive(Serialize)]
+struct AuditedSummary<'a> {
+    #[serde(flatten)]
+    summary: &'a AccountSummary,
+    /// Left out for accounts excluded from audits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<&'a AccountHealth>,
+}
+
+/// Longest value shown in a table cell before it is cut off
+const TABLE_CELL_WIDTH: usize = 32;
+
+#[derive(Args)]
+pub struct GetArgs {
+    /// Account ID or name
+    account: String,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Include the decrypted password, sensitive note and encrypted login steps in JSON or template output (text output always shows them)
+    #[arg(long)]
+    show_password: bool,
+    /// Print the account with a template instead, ie. "{name}\t{username}\t{url}".
+    /// Fields: id, name, username, password, url, description, login_steps, change_password_url, category.
+    /// {password} and encrypted fields print as ******** without --show-password
+    #[arg(long, conflicts_with = "format")]
+    template: Option<String>,
+}
+
+/// Shown for {password} and encrypted fields in a template without --show-password
+const REDACTED: &str = "********";
+
+/// Piece of a parsed `get --template`
+enum TemplatePart {
+    Literal(String),
+    Field(String),
+}
+
+const TEMPLATE_FIELDS: [&str; 9] = ["id", "name", "username", "password", "url", "description", "login_steps", "change_password_url", "category"];
+
+/// Splits a template into literal text and {field} placeholders
+/// 
+/// `{{` and `}}` are literal braces, `\t`, `\n` and `\\` are a tab, a newline and a backslash
+fn parse_template(template: &str) -> anyhow::Result<Vec<TemplatePart>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let field: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                if !TEMPLATE_FIELDS.contains(&field.as_str()) {
+                    anyhow::bail!("Unknown template field {{{}}}, available: {}", field, TEMPLATE_FIELDS.join(", "));
+                }
+                parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                parts.push(TemplatePart::Field(field));
+            }
+            '}' => anyhow::bail!("Unmatched }} in template, use }}}} for a literal brace"),
+            '\\' => match chars.next() {
+                Some('t') => literal.push('\t'),
+                Some('n') => literal.push('\n'),
+                Some('\\') => literal.push('\\'),
+                Some(other) => {
+                    literal.push('\\');
+                    literal.push(other);
+                }
+                None => literal.push('\\'),
+            },
+            c => literal.push(c),
+        }
+    }
+    parts.push(TemplatePart::Literal(literal));
+    Ok(parts)
+}
+
+/// Fills in a parsed template, missing optional fields are empty
+/// 
+/// Returns an error if a shown encrypted field doesn't decrypt with `master_password`
+fn render_template(parts: &[TemplatePart], account: &Account, master_password: &String, show_secrets: bool) -> anyhow::Result<String> {
+    // Encrypted fields are only decrypted when they are shown
+    let secret = |value: &String, encrypted: bool, field: &str| match (encrypted, show_secrets) {
+        (false, _) => Ok(value.clone()),
+        (true, true) => decrypt_field(master_password, value, field, &account.name),
+        (true, false) => Ok(REDACTED.to_string()),
+    };
+
+    let mut output = String::new();
+    for part in parts {
+        match part {
+            TemplatePart::Literal(text) => output.push_str(text),
+            TemplatePart::Field(field) => {
+                let value = match field.as_str() {
+                    "id" => account.id.to_string(),
+                    "name" => account.name.clone(),
+                    "username" => account.username.clone(),
+                    "password" => secret(&account.password, true, "password")?,
+                    "url" => account.url.clone().unwrap_or_default(),
+                    "description" => account.description.as_ref().map(|value| secret(value, account.encrypted_note, "description")).transpose()?.unwrap_or_default(),
+                    "login_steps" => account.login_steps.as_ref().map(|value| secret(value, account.encrypted_login_steps, "login steps")).transpose()?.unwrap_or_default(),
+                    "change_password_url" => account.change_password_url.clone().unwrap_or_default(),
+                    "category" => account.category.as_str().to_string(),
+                    _ => unreachable!("Checked by parse_template"),
+                };
+                output.push_str(&value);
+            }
+        }
+    }
+    Ok(output)
+}
+
+#[derive(Args)]
+pub struct UsernameArgs {
+    /// Account ID or name
+    account: String,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct ShareArgs {
+    /// Account ID or name
+    account: String,
+    /// Recipient's age public key (age1...)
+    #[arg(long)]
+    recipient: String,
+    /// File to write, must not exist yet
+    #[arg(short, long, value_name = "PATH")]
+    output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ImportSharedArgs {
+    /// File received from the sender
+    path: PathBuf,
+    /// Your age identity file, as created by age-keygen
+    #[arg(short, long, value_name = "PATH")]
+    identity: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ImportPassArgs {
+    /// Store directory, defaults to $PASSWORD_STORE_DIR or ~/.password-store
+    store: Option<PathBuf>,
+    /// Show each entry first and choose to include, skip or edit it
+    #[arg(long)]
+    review: bool,
+}
+
+#[derive(Args)]
+pub struct AuditArgs {
+    /// File to write, must not exist yet
+    #[arg(short, long, value_name = "PATH")]
+    output: PathBuf,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Vault file to compare with, it is only read. Asks for its master password as well
+    #[arg(value_name = "PATH")]
+    other: PathBuf,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable, same as the interactive menu
+    Text,
+    /// Machine readable JSON
+    Json,
+}
+
+/// JSON shape for `get`, the password is only present when requested
+#[derive(Serialize)]
+struct AccountOutput<'a> {
+    #[serde(flatten)]
+    account: &'a Account,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+}
+
+pub fn run_gen(args: &GenArgs) -> anyhow::Result<String> {
+    if args.passphrase {
+        return generate_passphrase(args.words, &args.separator);
+    }
+
+    let options = PasswordOptions {
+        length: args.length,
+        lowercase: !args.no_lowercase,
+        uppercase: !args.no_uppercase,
+        digits: !args.no_digits,
+        symbols: !args.no_symbols,
+        excluded: args.exclude.clone(),
+        exclude_ambiguous: args.no_ambiguous,
+        allow_short: args.allow_short,
+    };
+    if options.allow_short && options.length < GENERATED_MIN_LENGTH {
+        eprintln!("Warning: passwords shorter than {} characters are easy to guess.", GENERATED_MIN_LENGTH);
+    }
+    let password = generate_password(&options)?;
+    // Stderr, so stdout stays just the password for piping
+    eprintln!("{}", describe_password(&password));
+    Ok(password)
+}
+
+/// Fails with a hint to run the setup when the vault has no master account yet
+async fn require_vault(pool: &SqlitePool) -> anyhow::Result<()> {
+    if !master_exists(pool).await? {
+        anyhow::bail!("No vault initialized, run without a subcommand to set up a master account first");
+    }
+    Ok(())
+}
+
+/// Looks up an account by ID or name, whichever `account` parses as
+async fn find_account(pool: &SqlitePool, account: &str) -> anyhow::Result<Account> {
+    match account.parse::<i64>() {
+        Ok(id) => get_account_by_id(pool, id).await,
+        Err(_) => get_account_by_name(pool, &account.to_string()).await,
+    }
+    .map_err(|err| if is_not_found(&err) { anyhow::anyhow!("No account found matching: {}", account) } else { err })
+}
+
+pub async fn run_list(pool: &SqlitePool, args: &ListArgs) -> anyhow::Result<()> {
+    if args.table {
+        print_account_table(pool).await?;
+        return Ok(());
+    }
+
+    if args.most_used {
+        let accounts = list_accounts_by_use(pool).await?;
+        match args.format {
+            OutputFormat::Json => {
+                let used: Vec<_> = accounts
+                    .iter()
+                    .map(|(summary, access_count)| UsedSummary { summary, access_count: *access_count })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&used)?);
+            }
+            OutputFormat::Text => {
+                for (summary, access_count) in &accounts {
+                    print_account_summary_details(summary);
+                    println!("Times retrieved: {}", access_count);
+                    print_separator();
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let health = if args.audit {
+        require_vault(pool).await?;
+        let master = obtain_master_credentials(pool).await?;
+        Some(account_health(pool, &master.password).await?)
+    } else {
+        None
+    };
+
+    match args.format {
+        OutputFormat::Json => {
+            let summaries = list_accounts(pool).await?;
+            match &health {
+                Some(health) => {
+                    let audited: Vec<_> = summaries
+                        .iter()
+                        .map(|summary| AuditedSummary { summary, health: health.get(&summary.id) })
+                        .collect();
+                    println!("{}", serde_json::to_string_pretty(&audited)?);
+                }
+                None => println!("{}", serde_json::to_string_pretty(&summaries)?),
+            }
+        }
+        OutputFormat::Text => {
+            let groups = list_accounts_grouped(pool).await?;
+            if groups.is_empty() {
+                // Kept off stdout, which stays empty for scripts
+                eprintln!("No accounts stored yet.");
+            }
+            print_grouped_accounts(&groups, health.as_ref());
+        }
+    }
+    Ok(())
+}
+
+/// Prints one row per account, passwords are never part of the table
+async fn print_account_table(pool: &SqlitePool) -> anyhow::Result<()> {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .set_header(["ID", "Name", "Username", "URL"]);
+
+    for account in list_account_overviews(pool).await? {
+        table.add_row([
+            account.id.to_string(),
+            truncate_cell(&account.name),
+            truncate_cell(&account.username),
+            account.url.as_deref().map(truncate_cell).unwrap_or_else(|| "N/A".to_string()),
+        ]);
+    }
+
+    println!("{}", table);
+    Ok(())
+}
+
+fn truncate_cell(value: &str) -> String {
+    if value.chars().count() <= TABLE_CELL_WIDTH {
+        return value.to_string();
+    }
+    let truncated: String = value.chars().take(TABLE_CELL_WIDTH - 1).collect();
+    format!("{}…", truncated)
+}
+
+pub async fn run_get(pool: &SqlitePool, args: &GetArgs) -> anyhow::Result<()> {
+    require_vault(pool).await?;
+
+    let mut account = find_account(pool, &args.account).await?;
+
+    // Checked before logging in
+    let template = args.template.as_deref().map(parse_template).transpose()?;
+
+    let master = obtain_master_credentials(pool).await?;
+
+    if let Some(template) = template {
+        let mut output = render_template(&template, &account, &master.password, args.show_password)?;
+        println!("{}", output);
+        output.zeroize();
+        return Ok(());
+    }
+
+    match args.format {
+        OutputFormat::Json => {
+            let password = args.show_password
+                .then(|| decrypt_field(&master.password, &account.password, "password", &account.name))
+                .transpose()?;
+            // Sensitive notes are treated like the password
+            if account.encrypted_note {
+                account.description = match &account.description {
+                    Some(description) if args.show_password => Some(decrypt_field(&master.password, description, "description", &account.name)?),
+                    _ => None,
+                };
+            }
+            if account.encrypted_login_steps {
+                account.login_steps = match &account.login_steps {
+                    Some(steps) if args.show_password => Some(decrypt_field(&master.password, steps, "login steps", &account.name)?),
+                    _ => None,
+                };
+            }
+            let mut output = AccountOutput { account: &account, password };
+            let mut json = serde_json::to_string_pretty(&output)?;
+            output.password.zeroize();
+            println!("{}", json);
+            json.zeroize();
+            // Decrypted in place above
+            if account.encrypted_note {
+                account.description.zeroize();
+            }
+            if account.encrypted_login_steps {
+                account.login_steps.zeroize();
+            }
+        }
+        OutputFormat::Text => print_account_details(&account, &master.password, true),
+    }
+    Ok(())
+}
+
+/// Decrypts one encrypted field of an account, naming the field and account if it fails
+fn decrypt_field(master_password: &String, value: &str, field: &str, account_name: &str) -> anyhow::Result<String> {
+    try_decrypt_password(master_password, value).map_err(|_| {
+        anyhow::anyhow!("The {} of {} could not be decrypted, it may belong to another master account", field, account_name)
+    })
+}
+
+/// JSON shape for `username`
+#[derive(Serialize)]
+struct LoginOutput<'a> {
+    id: i64,
+    name: &'a str,
+    username: &'a str,
+    url: Option<&'a str>,
+}
+
+pub async fn run_username(pool: &SqlitePool, args: &UsernameArgs) -> anyhow::Result<()> {
+    let account = find_account(pool, &args.account).await?;
+
+    match args.format {
+        OutputFormat::Json => {
+            let output = LoginOutput {
+                id: account.id,
+                name: &account.name,
+                username: &account.username,
+                url: account.url.as_deref(),
+            };
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+        OutputFormat::Text => print_account_login(&account),
+    }
+    Ok(())
+}
+
+/// Undoes the double encryption older versions applied when an account was updated
+/// 
+/// A password that still decrypts cleanly after decrypting it once was encrypted twice,
+/// the once-decrypted value is the correct ciphertext and is stored in its place
+pub async fn run_repair(pool: &SqlitePool) -> anyhow::Result<()> {
+    require_vault(pool).await?;
+
+    let master = obtain_master_credentials(pool).await?;
+
+    let mut affected = Vec::new();
+    let mut unreadable = 0;
+    for summary in list_accounts(pool).await? {
+        let mut account = get_account_by_id(pool, summary.id).await?;
+        match try_decrypt_password(&master.password, &account.password) {
+            Ok(mut inner) => {
+                if try_decrypt_password(&master.password, &inner).is_ok() {
+                    std::mem::swap(&mut account.password, &mut inner);
+                    affected.push(account);
+                }
+                inner.zeroize();
+            }
+            Err(_) => unreadable += 1,
+        }
+    }
+
+    if unreadable > 0 {
+        println!("{} password(s) could not be decrypted with this master password and were skipped.", unreadable);
+    }
+    if affected.is_empty() {
+        println!("No double-encrypted passwords found.");
+        return Ok(());
+    }
+
+    println!("Double-encrypted passwords found:");
+    for account in &affected {
+        println!("{}. {}", account.id, account.name);
+    }
+    if !confirm(&format!("Repair {} account(s)?", affected.len()))? {
+        println!("Nothing was changed.");
+        return Ok(());
+    }
+
+    let mut repaired = 0;
+    for account in &affected {
+        match update_account(pool, account).await {
+            Ok(_) => repaired += 1,
+            Err(e) => eprintln!("Failed to repair account {}: {}", account.id, e),
+        }
+    }
+    println!("Repaired {} of {} account(s).", repaired, affected.len());
+    Ok(())
+}
+
+pub async fn run_scan_plaintext(pool: &SqlitePool) -> anyhow::Result<()> {
+    require_vault(pool).await?;
+
+    let master = obtain_master_credentials(pool).await?;
+    let suspicious = scan_for_plaintext(pool, &master.password).await?;
+    if suspicious.is_empty() {
+        println!("No unencrypted passwords found in the database file.");
+        return Ok(());
+    }
+
+    println!("Passwords that may be stored unencrypted:");
+    for id in &suspicious {
+        match get_account_by_id(pool, *id).await {
+            Ok(account) => println!("{}. {}", account.id, account.name),
+            Err(_) => println!("{}.", id),
+        }
+    }
+    println!("Set a new password for these accounts from the menu (option 4) so it is stored encrypted.");
+    anyhow::bail!("{} account(s) failed the check", suspicious.len())
+}
+
+pub async fn run_share(pool: &SqlitePool, args: &ShareArgs) -> anyhow::Result<()> {
+    require_vault(pool).await?;
+
+    let account = find_account(pool, &args.account).await?;
+
+    let master = reauthenticate(pool).await?;
+    export_shared(pool, account.id, &master.password, &args.recipient, &args.output).await?;
+
+    eprintln!("Shared {} to {}, only the recipient can decrypt it.", account.name, args.output.display());
+    Ok(())
+}
+
+pub async fn run_import_shared(pool: &SqlitePool, args: &ImportSharedArgs) -> anyhow::Result<()> {
+    require_vault(pool).await?;
+
+    let mut private_key = read_identity_file(&args.identity)?;
+    let shared = import_shared(&args.path, &private_key);
+    private_key.zeroize();
+    let shared = shared?;
+
+    if get_account_by_name(pool, &shared.name).await.is_ok() {
+        anyhow::bail!("An account named {} already exists, rename or delete it first", shared.name);
+    }
+
+    // Encrypt password before adding
+    let master = obtain_master_credentials(pool).await?;
+    let description = match &shared.description {
+        Some(description) if shared.encrypted_note => Some(encrypt_password(&master.password, description)),
+        description => description.clone(),
+    };
+    let mut account = Account::new(
+        shared.name.clone(),
+        shared.username.clone(),
+        encrypt_password(&master.password, &shared.password),
+        shared.url.clone(),
+        description,
+    );
+    account.encrypted_note = shared.encrypted_note;
+    account.login_steps = match &shared.login_steps {
+        Some(steps) if shared.encrypted_login_steps => Some(encrypt_password(&master.password, steps)),
+        steps => steps.clone(),
+    };
+    account.encrypted_login_steps = shared.encrypted_login_steps;
+    account.change_password_url = shared.change_password_url.clone();
+
+    add_account(pool, &account).await?;
+    eprintln!("Imported {}.", account.name);
+    Ok(())
+}
+
+pub async fn run_import_pass(pool: &SqlitePool, args: &ImportPassArgs) -> anyhow::Result<()> {
+    require_vault(pool).await?;
+    let store = match &args.store {
+        Some(store) => store.clone(),
+        None => default_store_path().ok_or_else(|| anyhow::anyhow!("No store given and HOME is not set"))?,
+    };
+    if !store.is_dir() {
+        anyhow::bail!("{} is not a directory", store.display());
+    }
+
+    let master = obtain_master_credentials(pool).await?;
+    let summary = if args.review {
+        import_pass_store(pool, &store, &master.password, review_import_candidate).await?
+    } else {
+        import_pass_store(pool, &store, &master.password, |_| Ok(ReviewChoice::IncludeAll)).await?
+    };
+
+    for name in &summary.imported {
+        eprintln!("Imported {}.", name);
+    }
+    for (name, reason) in &summary.skipped {
+        eprintln!("Skipped {}: {}", name, reason);
+    }
+    eprintln!("{} imported, {} skipped.", summary.imported.len(), summary.skipped.len());
+    Ok(())
+}
+
+pub async fn run_audit(pool: &SqlitePool, args: &AuditArgs) -> anyhow::Result<()> {
+    require_vault(pool).await?;
+    // Checked before the slow part, the file is still created with create_new
+    if args.output.exists() {
+        anyhow::bail!("{} already exists, choose a new file for the report", args.output.display());
+    }
+
+    let master = obtain_master_credentials(pool).await?;
+    let report = generate_audit_report(pool, &master.password).await?;
+    let contents = match args.format {
+        OutputFormat::Json => report.to_json()?,
+        OutputFormat::Text => report.to_text(),
+    };
+    write_audit_report(&args.output, &contents)?;
+
+    eprintln!("Checked {} account(s), {} issue(s) written to {}.", report.accounts_checked, report.issues.len(), args.output.display());
+    Ok(())
+}
+
+pub async fn run_diff(pool: &SqlitePool, args: &DiffArgs) -> anyhow::Result<()> {
+    // SQLCipher files can't be attached without their key
+    if cfg!(feature = "sqlcipher") {
+        anyhow::bail!("diff is not available in SQLCipher builds");
+    }
+    require_vault(pool).await?;
+
+    // Copied into memory so the other vault is never migrated or written to
+    let other_pool = initialize_memory_db().await?;
+    load_vault_into_memory(&other_pool, &args.other).await?;
+    if !master_exists(&other_pool).await? {
+        anyhow::bail!("{} has no master account, it is not a vault", args.other.display());
+    }
+
+    let master = obtain_master_credentials(pool).await?;
+    let mut other_master = obtain_other_master_password(&other_pool, &args.other).await?;
+    let diff = diff_vaults(pool, &other_pool, &master.password, &other_master).await;
+    other_master.zeroize();
+    other_pool.close().await;
+    let diff = diff?;
+
+    if diff.is_empty() {
+        println!("No differences, {} account(s) are the same in both vaults.", diff.unchanged);
+        return Ok(());
+    }
+    if !diff.only_in_a.is_empty() {
+        println!("Only in this vault:");
+        for name in &diff.only_in_a {
+            println!("  {}", name);
+        }
+    }
+    if !diff.only_in_b.is_empty() {
+        println!("Only in {}:", args.other.display());
+        for name in &diff.only_in_b {
+            println!("  {}", name);
+        }
+    }
+    if !diff.changed.is_empty() {
+        println!("Changed:");
+        for account in &diff.changed {
+            println!("  {}: {} differs", account.name, account.fields.join(", "));
+        }
+    }
+    println!("{} account(s) are the same in both vaults.", diff.unchanged);
+    Ok(())
+}
@@ -1,10 +1,93 @@
 // Path to the SQLite database
 pub const DB_PATH: &str = "./passwords.db"; 
 
-// Enables or disables security features that don't work in the debugger
-// Secure password input does not work in the debugger (rpassword)
+// Milliseconds SQLite waits on a lock held by another instance before giving up
+pub const DB_BUSY_TIMEOUT_MS: u64 = 5000;
+
+// Times a write is retried when the database is still locked after the busy timeout
+// The delay starts at DB_LOCKED_RETRY_DELAY_MS and doubles after each retry
+pub const DB_LOCKED_RETRIES: u32 = 3;
+pub const DB_LOCKED_RETRY_DELAY_MS: u64 = 100;
+
+// Prints verbose diagnostics to stderr (database path, migrations, lock retries, keyring lookups)
+// Secrets are never printed. Can also be turned on at runtime by setting PM_DEBUG=1
 pub const DEBUG_FLAG: bool = false; 
 
+// Reads passwords as visible plain lines, secure password input does not work in the debugger (rpassword)
+// Only takes effect in debug builds, release builds always hide password input
+pub const PLAIN_PASSWORD_INPUT: bool = false;
+
 // Enables or disables single master account mode
-// If there is only 1 master, "default" username will be used
-pub const SINGLE_MASTER_FLAG: bool = true;
\ No newline at end of file
+// If there is only 1 master, the username prompt is skipped
+// The master is created as "default" and can be renamed from the menu
+pub const SINGLE_MASTER_FLAG: bool = true;
+
+// Ask for the optional URL and description when adding an account
+// When false they are left empty, and can be filled in later with "Update an account"
+pub const PROMPT_OPTIONAL_FIELDS: bool = true;
+
+// Ask for the master password again before deleting, even though the menu is already unlocked
+// Changing the master password and exporting (extracting an attachment, sharing) always ask,
+// they need it to re-encrypt or decrypt
+pub const REAUTH_DELETE_ACCOUNT: bool = false;
+pub const REAUTH_DELETE_ATTACHMENT: bool = false;
+
+// Look up account names ignoring case, so "google" finds "Google"
+// An exact match always wins when names only differ in case
+pub const CASE_INSENSITIVE_NAMES: bool = true;
+
+// Separates namespaces in account names, ie. "work/github" and "personal/github"
+// Namespaces can be nested, "work/team/github"
+pub const NAMESPACE_SEPARATOR: &str = "/";
+
+// Pressing Enter on an empty menu choice runs the previous option again,
+// ie. to add several accounts in a row. Off by default so a stray Enter does nothing
+pub const REPEAT_LAST_ACTION: bool = false;
+
+// Count how often each account is retrieved from the menu, for `list --most-used`
+// The counts are stored unencrypted in the vault, turn this off to keep no usage data
+pub const TRACK_ACCOUNT_ACCESS: bool = true;
+
+// Back up the vault into this directory when the menu is exited, ie. Some("./backups")
+// Backups are named after the vault file with a timestamp and stay encrypted like the vault itself
+// None turns automatic backups off
+pub const AUTO_BACKUP_DIR: Option<&str> = None;
+// Automatic backups of the same vault kept in AUTO_BACKUP_DIR, the oldest are deleted first
+pub const AUTO_BACKUP_KEEP: usize = 5;
+
+// Show the password of an account retrieved from the menu (option 3) for PASSWORD_REVEAL_SECS only,
+// with a countdown, after which its line is cleared. The rest of the account stays on screen
+// When false, or when stdout isn't a terminal, the password is printed with the other fields and left there
+pub const TIMED_PASSWORD_REVEAL: bool = false;
+pub const PASSWORD_REVEAL_SECS: u64 = 10;
+
+// Number of accounts shown right after logging in, as a quick check that the vault is intact
+// 0 turns the preview off
+pub const LOGIN_PREVIEW_COUNT: i64 = 0;
+
+// Seconds to wait at the master password prompt before giving up and exiting
+// None waits forever
+pub const MASTER_PROMPT_TIMEOUT_SECS: Option<u64> = None;
+
+// Minimum length required for a new master password
+pub const MASTER_MIN_LENGTH: usize = 12;
+
+// Minimum number of character classes (lowercase, uppercase, digits, symbols)
+// a new master password must mix. Set to 1 to disable the check
+pub const MASTER_MIN_CHARACTER_CLASSES: usize = 3;
+
+// Shortest password the generator produces unless explicitly overridden
+// (--allow-short for `gen`, a confirmation for account policies)
+pub const GENERATED_MIN_LENGTH: usize = 8;
+
+// Largest file that can be attached to an account, in bytes
+// Attachments are read into memory to encrypt, so keep this small
+pub const MAX_ATTACHMENT_SIZE: usize = 1024 * 1024;
+
+// Data quality checks for the "List incomplete accounts" menu option
+// Flag accounts without a URL / without a description
+pub const REQUIRE_ACCOUNT_URL: bool = true;
+pub const REQUIRE_ACCOUNT_DESCRIPTION: bool = false;
+// Flag handles that match the name part of an email used as a username elsewhere,
+// ie. "alice" on one account and "alice@example.com" on another
+pub const FLAG_MIXED_USERNAMES: bool = true;
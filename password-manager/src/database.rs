@@ -1,20 +1,71 @@
-use std::str::FromStr;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::{collections::{BTreeMap, HashSet}, fs::{self, DirBuilder, OpenOptions}, future::Future, path::{Path, PathBuf}, str::FromStr, sync::OnceLock, time::Duration};
 
+use anyhow::Context;
+
+use serde::Serialize;
 use sqlx::prelude::FromRow;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use sqlx::types::chrono::{Local, NaiveDate, NaiveDateTime};
+use subtle::ConstantTimeEq;
+use sqlx::sqlite::{SqliteConnection, SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
 use zeroize::Zeroize;
-use anyhow;
 
-use crate::{compile_config::DB_PATH, encryption::{hash_master_password, verify_master_password}};
+use crate::{diagnostics::debug_log, compile_config::{CASE_INSENSITIVE_NAMES, DB_BUSY_TIMEOUT_MS, DB_LOCKED_RETRIES, DB_LOCKED_RETRY_DELAY_MS, FLAG_MIXED_USERNAMES, NAMESPACE_SEPARATOR, REQUIRE_ACCOUNT_DESCRIPTION, REQUIRE_ACCOUNT_URL, TRACK_ACCOUNT_ACCESS}, encryption::{decrypt_bytes, encrypt_bytes, encrypt_password, looks_like_ciphertext, try_decrypt_password, verify_master_password}, generator::PasswordOptions};
 
-#[derive(Debug, FromRow)]
+/// The password is only ever serialized through an explicit field, never as stored ciphertext
+#[derive(Debug, FromRow, Serialize)]
 pub struct Account {
     pub id: i64,  // SQLite uses `i64` for integer keys
     pub name: String,
     pub username: String,
+    #[serde(skip)]
     pub password: String,
     pub url: Option<String>,
     pub description: Option<String>,
+    /// The description is a sensitive note, stored encrypted like the password
+    pub encrypted_note: bool,
+    /// Steps to follow when logging in, ie. "enter username, click Next, then password"
+    pub login_steps: Option<String>,
+    /// The login steps are stored encrypted like the password
+    pub encrypted_login_steps: bool,
+    /// Page where the site's password is changed, offered when the account is due for review
+    pub change_password_url: Option<String>,
+    pub category: Category,
+}
+
+/// Fixed bucket an account belongs to, listings are grouped by it in this order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Category {
+    #[default]
+    Personal,
+    Work,
+    Shared,
+}
+
+impl Category {
+    pub const ALL: [Category; 3] = [Category::Personal, Category::Work, Category::Shared];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Personal => "personal",
+            Category::Work => "work",
+            Category::Shared => "shared",
+        }
+    }
+}
+
+impl FromStr for Category {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Category::ALL
+            .into_iter()
+            .find(|category| category.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| anyhow::anyhow!("Unknown category {}, expected one of: personal, work, shared", s))
+    }
 }
 
 impl Account {
@@ -26,6 +77,11 @@ impl Account {
             password,
             url,
             description,
+            encrypted_note: false,
+            login_steps: None,
+            encrypted_login_steps: false,
+            change_password_url: None,
+            category: Category::default(),
         }
     }
 }
@@ -41,11 +97,58 @@ impl Drop for Account {
     }
 }
 
-#[derive(Debug, FromRow)]
+#[derive(Debug, FromRow, Serialize)]
 pub struct AccountSummary {
     pub id: i64,
     pub name: String,
+    /// Left empty when `encrypted_note` is set, summaries never carry the ciphertext
     pub description: Option<String>,
+    pub encrypted_note: bool,
+}
+
+impl AccountSummary {
+    /// Namespace part of the name, ie. "work" for "work/github"
+    pub fn namespace(&self) -> Option<&str> {
+        self.name.rsplit_once(NAMESPACE_SEPARATOR).map(|(namespace, _)| namespace)
+    }
+}
+
+/// A file stored with an account, the data is encrypted with [`crate::encryption::encrypt_bytes()`]
+#[derive(Debug, FromRow)]
+pub struct Attachment {
+    pub id: i64,
+    pub account_id: i64,
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn new(account_id: i64, filename: String, data: Vec<u8>) -> Self {
+        Attachment {
+            id: 0, // Placeholder value, ID will be assigned automatically
+            account_id,
+            filename,
+            data,
+        }
+    }
+}
+
+/// Attachment without its data, for listing
+#[derive(Debug, FromRow)]
+pub struct AttachmentSummary {
+    pub id: i64,
+    pub filename: String,
+    /// Size of the original file in bytes
+    pub size: i64,
+}
+
+/// Account without its password or description, for table listings
+#[derive(Debug, FromRow)]
+pub struct AccountOverview {
+    pub id: i64,
+    pub name: String,
+    pub username: String,
+    pub url: Option<String>,
 }
 
 // For now, this will be used to define a set of users who are able to access the passwords
@@ -54,7 +157,9 @@ pub struct AccountSummary {
 pub struct Master {
     pub id: i64,
     pub username: String,
-    pub password: String
+    pub password: String,
+    /// Password hash and encryption keys also depend on a key file
+    pub keyfile_required: bool
 }
 
 impl Master {
@@ -62,7 +167,8 @@ impl Master {
         Master {
             id: 0, // Placeholder value, ID will be assigned automatically
             username,
-            password
+            password,
+            keyfile_required: false
         }
     }
 }
@@ -75,11 +181,464 @@ impl Drop for Master {
     }
 }
 
-pub async fn initialize_db() -> anyhow::Result<SqlitePool> {
-    let options = SqliteConnectOptions::from_str(DB_PATH)?
-        .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
-    let pool = SqlitePool::connect_with(options).await?;
+/// Creates missing parent directories and an empty database file before SQLite opens it
+/// 
+/// On Unix the file is created with mode 0600 so other users can't read it,
+/// SQLite gives the -wal and -shm files the same permissions as the database file
+pub fn prepare_db_file(path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+
+    if !path.exists() {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(path)
+            .with_context(|| format!("Failed to create database file {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Restricts the database file and its -wal/-shm files to the owner (mode 0600)
+/// 
+/// Files created by [`prepare_db_file()`] already are, this catches older or copied vaults
+/// 
+/// Only supported on Unix, on other platforms the files are left as they are
+/// 
+/// # Returns
+/// 
+/// Returns the files that were readable by other users before
+#[cfg(unix)]
+pub fn restrict_db_file_permissions(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut loosened = Vec::new();
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file_path = path.as_os_str().to_owned();
+        file_path.push(suffix);
+        let file_path = PathBuf::from(file_path);
+
+        // The -wal/-shm files don't always exist
+        let Ok(metadata) = fs::metadata(&file_path) else {
+            continue;
+        };
+
+        if metadata.permissions().mode() & 0o077 != 0 {
+            fs::set_permissions(&file_path, fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("Failed to set permissions on {}", file_path.display()))?;
+            loosened.push(file_path);
+        }
+    }
+
+    Ok(loosened)
+}
+
+#[cfg(not(unix))]
+pub fn restrict_db_file_permissions(_path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(Vec::new())
+}
+
+/// Lock file taken by [`acquire_vault_lock()`], set for the rest of the session
+static VAULT_LOCK: OnceLock<PathBuf> = OnceLock::new();
+
+/// Takes `<database>.lock` so a second interactive instance refuses to open the same vault
+/// 
+/// The lock file holds the owner's PID and is removed by [`release_vault_lock()`].
+/// A lock left behind by a process that no longer runs (ie. it was killed) is taken over on Unix,
+/// elsewhere it has to be deleted by hand
+pub fn acquire_vault_lock(db_path: &Path) -> anyhow::Result<()> {
+    let mut lock_path = db_path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+
+    // Second try after removing a stale lock
+    for _ in 0..2 {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        match options.open(&lock_path) {
+            Ok(mut file) => {
+                use std::io::Write;
+                let _ = VAULT_LOCK.set(lock_path.clone());
+                write!(file, "{}", std::process::id())
+                    .with_context(|| format!("Failed to write lock file {}", lock_path.display()))?;
+                return Ok(());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to create lock file {}", lock_path.display())),
+        }
+
+        // A lock that is still being written has no PID yet and counts as held
+        let owner = fs::read_to_string(&lock_path).ok().and_then(|pid| pid.trim().parse::<u32>().ok());
+        match owner {
+            Some(pid) if !process_is_running(pid) => {
+                debug_log!("Removing stale lock {} left by process {}", lock_path.display(), pid);
+                fs::remove_file(&lock_path)
+                    .with_context(|| format!("Failed to remove stale lock file {}", lock_path.display()))?;
+            }
+            Some(pid) => anyhow::bail!("The vault is already open in another instance (process {}), close it first", pid),
+            None => break,
+        }
+    }
+
+    anyhow::bail!("The vault is already open in another instance. If none is running, delete {}", lock_path.display())
+}
+
+/// Removes the lock file, if this process holds one. Called on every exit, Ctrl-C included
+pub fn release_vault_lock() {
+    if let Some(path) = VAULT_LOCK.get() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(unix)]
+fn process_is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists, EPERM means it does but belongs to another user
+    unsafe { libc::kill(pid, 0) == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+#[cfg(not(unix))]
+fn process_is_running(_pid: u32) -> bool {
+    true
+}
+
+/// Path of the file the pool is connected to
+pub async fn db_file_path(pool: &SqlitePool) -> anyhow::Result<PathBuf> {
+    let file: String = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+        .fetch_one(pool)
+        .await?;
+
+    if file.is_empty() {
+        anyhow::bail!("The database is in memory, it has no file");
+    }
+    Ok(PathBuf::from(file))
+}
+
+/// Writes a consistent copy of the whole vault to `dest` with VACUUM INTO
+/// 
+/// Entries stay encrypted in the copy, it opens with the master password current at the time.
+/// On Unix the file is created with mode 0600 before SQLite writes to it
+pub async fn backup_vault(pool: &SqlitePool, dest: &Path) -> anyhow::Result<()> {
+    let dest_uri = format!("{}?mode=rw", sqlite_file_uri(dest)?);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(dest)
+        .with_context(|| format!("Failed to create backup file {}", dest.display()))?;
+
+    // VACUUM INTO accepts an existing file as long as it is empty
+    if let Err(err) = sqlx::query("VACUUM INTO ?").bind(dest_uri).execute(pool).await {
+        let _ = fs::remove_file(dest);
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+/// Backs up the vault file into `dir` as `<vault file>.<timestamp>.bak` and deletes all but the newest `keep`
+/// backups of the same vault there, see [`backup_vault()`]
+/// 
+/// Only files named after this vault are rotated, so several vaults can share a directory
+/// 
+/// # Returns
+/// 
+/// Returns the new backup and the backups that were deleted
+pub async fn auto_backup(pool: &SqlitePool, dir: &Path, keep: usize) -> anyhow::Result<(PathBuf, Vec<PathBuf>)> {
+    let db_path = db_file_path(pool).await?;
+    let db_name = db_path.file_name().context("Vault path has no file name")?.to_string_lossy().into_owned();
+
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(dir)
+        .with_context(|| format!("Failed to create backup directory {}", dir.display()))?;
+
+    let backup_path = dir.join(format!("{}.{}.bak", db_name, Local::now().format("%Y%m%d-%H%M%S")));
+    backup_vault(pool, &backup_path).await?;
+
+    let removed = rotate_backups(dir, &db_name, keep)
+        .with_context(|| format!("Backed up to {}, but failed to remove old backups", backup_path.display()))?;
+    Ok((backup_path, removed))
+}
+
+/// Deletes the oldest `<db_name>.<timestamp>.bak` files in `dir` until `keep` are left
+fn rotate_backups(dir: &Path, db_name: &str, keep: usize) -> anyhow::Result<Vec<PathBuf>> {
+    let prefix = format!("{}.", db_name);
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_backup = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+            name.strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".bak"))
+                .is_some_and(|timestamp| NaiveDateTime::parse_from_str(timestamp, "%Y%m%d-%H%M%S").is_ok())
+        });
+        if is_backup && path.is_file() {
+            backups.push(path);
+        }
+    }
+
+    // The timestamps sort in date order, newest last
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+    for path in &removed {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(removed)
+}
+
+/// Size of the database before and after [`vacuum_db()`], in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct VacuumReport {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+impl VacuumReport {
+    pub fn reclaimed(&self) -> u64 {
+        self.size_before.saturating_sub(self.size_after)
+    }
+}
+
+/// Rebuilds the database file with `VACUUM`, returning the space freed by deleted accounts and attachments
+/// 
+/// Runs `PRAGMA optimize` afterwards so the query planner statistics match the rebuilt tables.
+/// Sizes are counted in pages rather than read from the file, so pages still in the WAL are included
+pub async fn vacuum_db(pool: &SqlitePool) -> anyhow::Result<VacuumReport> {
+    // Same connection throughout, VACUUM can't run inside a transaction and an in-memory vault only exists on it
+    let mut conn = pool.acquire().await?;
+
+    let size_before = database_size(&mut conn).await?;
+    sqlx::query("VACUUM").execute(&mut *conn).await
+        .context("VACUUM failed, is another program using the vault?")?;
+    // Measured before optimizing, which may add a page or two of statistics
+    let size_after = database_size(&mut conn).await?;
+    sqlx::query("PRAGMA optimize").execute(&mut *conn).await?;
+
+    debug_log!("Vacuumed database from {} to {} bytes", size_before, size_after);
+    Ok(VacuumReport { size_before, size_after })
+}
+
+async fn database_size(conn: &mut SqliteConnection) -> anyhow::Result<u64> {
+    let page_count: i64 = sqlx::query_scalar("PRAGMA page_count").fetch_one(&mut *conn).await?;
+    let page_size: i64 = sqlx::query_scalar("PRAGMA page_size").fetch_one(&mut *conn).await?;
+    Ok((page_count * page_size) as u64)
+}
+
+/// `file:` URI for a path, for ATTACH and VACUUM INTO
+/// 
+/// Both open the file with the flags of the main connection, so from an in-memory database
+/// a plain path is opened in memory too. A URI with an explicit `mode=` opens the file itself
+fn sqlite_file_uri(path: &Path) -> anyhow::Result<String> {
+    let path_str = path.to_str().context("Path is not valid UTF-8")?;
+    Ok(format!("file:{}", path_str.replace('%', "%25").replace('?', "%3f").replace('#', "%23")))
+}
+
+fn db_options(path: &Path) -> SqliteConnectOptions {
+    SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
+        .busy_timeout(Duration::from_millis(DB_BUSY_TIMEOUT_MS))
+}
+
+/// Whether an error is SQLITE_BUSY or SQLITE_LOCKED, ie. another instance holds the database
+fn is_locked_error(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(db_err) => {
+            // Extended result codes keep the primary code in the low byte
+            let code = db_err.code().and_then(|code| code.parse::<i32>().ok());
+            matches!(code.map(|code| code & 0xff), Some(5 | 6))
+        }
+        _ => false,
+    }
+}
+
+/// Runs a database operation, retrying with exponential backoff while the database is locked
+/// 
+/// busy_timeout already waits on most locks, this covers the cases SQLite returns
+/// SQLITE_BUSY right away, such as a stale WAL snapshot
+/// 
+/// Retries [`DB_LOCKED_RETRIES`] times, starting at [`DB_LOCKED_RETRY_DELAY_MS`] and doubling
+pub async fn with_retry<T, F, Fut>(mut op: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut delay = Duration::from_millis(DB_LOCKED_RETRY_DELAY_MS);
+    let mut retries_left = DB_LOCKED_RETRIES;
+
+    loop {
+        match op().await {
+            Err(err) if retries_left > 0 && is_locked_error(&err) => {
+                retries_left -= 1;
+                debug_log!("Database is locked, retrying in {:?} ({} retries left)", delay, retries_left);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg_attr(feature = "sqlcipher", allow(dead_code))]
+pub async fn initialize_db(path: &Path) -> anyhow::Result<SqlitePool> {
+    let pool = SqlitePool::connect_with(db_options(path)).await?;
+
+    create_tables(&pool).await?;
+
+    Ok(pool)
+}
 
+/// Wraps a value in single quotes for use in a PRAGMA statement, which can't take bound parameters
+#[cfg(feature = "sqlcipher")]
+fn sql_string_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Opens the database file encrypted with SQLCipher
+/// 
+/// The master password is passed as the SQLCipher passphrase, 
+/// SQLCipher derives the page encryption key from it and the salt in the file header
+/// 
+/// A new file is encrypted on creation, an existing plaintext file can't be opened this way
+#[cfg(feature = "sqlcipher")]
+pub async fn initialize_encrypted_db(path: &Path, master_password: &str) -> anyhow::Result<SqlitePool> {
+    let options = db_options(path).pragma("key", sql_string_literal(master_password));
+
+    // A rekey only applies to the connection it runs on, and new connections
+    // would reuse the old key from the options, so keep one connection open for good
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .map_err(|e| anyhow::anyhow!("Unable to unlock database, is the master password correct? ({})", e))?;
+
+    create_tables(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Re-encrypts the whole SQLCipher database under a new master password
+#[cfg(feature = "sqlcipher")]
+pub async fn rekey_db(pool: &SqlitePool, new_master_password: &str) -> anyhow::Result<()> {
+    let mut statement = format!("PRAGMA rekey = {}", sql_string_literal(new_master_password));
+    let result = sqlx::query(&statement).execute(pool).await;
+    statement.zeroize();
+
+    result?;
+    Ok(())
+}
+
+/// Opens a throwaway in-memory database with the full schema
+/// 
+/// Nothing is written to disk, the data is gone once the pool is dropped
+pub async fn initialize_memory_db() -> anyhow::Result<SqlitePool> {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:")?;
+
+    // Every connection to :memory: opens its own empty database,
+    // so the pool must hold on to exactly one connection
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await?;
+
+    create_tables(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Copies every table of the vault file `source` into an in-memory database from [`initialize_memory_db()`]
+/// 
+/// The file is only read. Columns the file doesn't have yet (older vaults) get their defaults
+pub async fn load_vault_into_memory(pool: &SqlitePool, source: &Path) -> anyhow::Result<()> {
+    if !source.is_file() {
+        anyhow::bail!("{} is not a vault file", source.display());
+    }
+    let quote = |identifier: &str| format!("\"{}\"", identifier.replace('"', "\"\""));
+
+    // The pool has a single connection, so the ATTACH and the pragmas apply to every query below
+    let source_uri = format!("{}?mode=ro", sqlite_file_uri(source)?);
+    sqlx::query("ATTACH DATABASE ? AS source").bind(source_uri).execute(pool).await
+        .with_context(|| format!("Failed to open {}", source.display()))?;
+    // Attachments reference accounts, tables are copied in any order
+    sqlx::query("PRAGMA foreign_keys = OFF").execute(pool).await?;
+
+    let result: anyhow::Result<()> = async {
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM source.sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+                AND name IN (SELECT name FROM main.sqlite_master WHERE type = 'table')"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut tx = pool.begin().await?;
+        for table in tables {
+            let columns: Vec<String> = sqlx::query_scalar(
+                "SELECT name FROM pragma_table_info(?1, 'source')
+                    WHERE name IN (SELECT name FROM pragma_table_info(?1, 'main'))"
+            )
+            .bind(&table)
+            .fetch_all(&mut *tx)
+            .await?;
+            let columns = columns.iter().map(|column| quote(column)).collect::<Vec<_>>().join(", ");
+
+            debug_log!("Loading table {} into memory", table);
+            sqlx::query(&format!(
+                "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM source.{table}",
+                table = quote(&table),
+                columns = columns
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+    .await;
+
+    sqlx::query("PRAGMA foreign_keys = ON").execute(pool).await?;
+    sqlx::query("DETACH DATABASE source").execute(pool).await?;
+    result.with_context(|| format!("Failed to load {}", source.display()))
+}
+
+/// Writes the whole in-memory vault to `dest`, replacing the file if it exists
+/// 
+/// The copy is written next to `dest` first and renamed over it, so a failed save leaves the old file as it was
+pub async fn save_memory_vault(pool: &SqlitePool, dest: &Path) -> anyhow::Result<()> {
+    let mut temp_path = dest.as_os_str().to_owned();
+    temp_path.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = PathBuf::from(temp_path);
+
+    backup_vault(pool, &temp_path).await?;
+    if let Err(err) = fs::rename(&temp_path, dest) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err).with_context(|| format!("Failed to save the vault to {}", dest.display()));
+    }
+
+    Ok(())
+}
+
+async fn create_tables(pool: &SqlitePool) -> anyhow::Result<()> {
     sqlx::query!(
         "CREATE TABLE IF NOT EXISTS accounts (
             id INTEGER PRIMARY KEY,
@@ -87,34 +646,83 @@ pub async fn initialize_db() -> anyhow::Result<SqlitePool> {
             url TEXT,
             username TEXT NOT NULL,
             password TEXT NOT NULL,
-            description TEXT
+            description TEXT,
+            generation_policy TEXT,
+            encrypted_note INTEGER NOT NULL DEFAULT 0,
+            expires_at TEXT,
+            login_steps TEXT,
+            encrypted_login_steps INTEGER NOT NULL DEFAULT 0,
+            change_password_url TEXT,
+            category TEXT NOT NULL DEFAULT 'personal',
+            exclude_from_audit INTEGER NOT NULL DEFAULT 0,
+            access_count INTEGER NOT NULL DEFAULT 0
         )"
     )
-    .execute(&pool)
+    .execute(pool)
     .await?; 
 
     sqlx::query!(
         "create table if not exists masters (
             id integer primary key,
             username text not null,
-            password text not null
+            password text not null,
+            keyfile_required integer not null default 0
         )"
     )
-    .execute(&pool)
+    .execute(pool)
     .await?; 
 
-    // Insert the default account only if there are no accounts in the table
-    let default_master_password_hash = hash_master_password(&"changethis".to_string()).expect("Error hashing password!");
+    // Attachments go away with their account
     sqlx::query!(
-        "insert into masters (username, password)
-        select 'default', ?
-        where not exists (select 1 from masters)",
-        default_master_password_hash
+        "CREATE TABLE IF NOT EXISTS attachments (
+            id INTEGER PRIMARY KEY,
+            account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+            filename TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            data BLOB NOT NULL
+        )"
     )
-    .execute(&pool)
+    .execute(pool)
     .await?; 
 
-    Ok(pool)
+    // Columns added after the initial schema, for databases created before them
+    add_column_if_missing(pool, "masters", "keyfile_required", "integer not null default 0").await?;
+    add_column_if_missing(pool, "accounts", "generation_policy", "TEXT").await?;
+    add_column_if_missing(pool, "accounts", "encrypted_note", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "accounts", "expires_at", "TEXT").await?;
+    add_column_if_missing(pool, "accounts", "login_steps", "TEXT").await?;
+    add_column_if_missing(pool, "accounts", "encrypted_login_steps", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "accounts", "change_password_url", "TEXT").await?;
+    add_column_if_missing(pool, "accounts", "category", "TEXT NOT NULL DEFAULT 'personal'").await?;
+    add_column_if_missing(pool, "accounts", "exclude_from_audit", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "accounts", "access_count", "INTEGER NOT NULL DEFAULT 0").await?;
+
+    // The first master is created by the setup flow in the UI, see master_exists()
+
+    Ok(())
+}
+
+/// Adds a column to an existing table unless it is already there
+/// 
+/// SQLite has no ADD COLUMN IF NOT EXISTS, so check the table info first
+async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, definition: &str) -> anyhow::Result<()> {
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)"
+    )
+    .bind(table)
+    .bind(column)
+    .fetch_one(pool)
+    .await?;
+
+    if !exists {
+        // Identifiers can't be bound, these only ever come from the constants in create_tables()
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+        debug_log!("Added column {}.{}", table, column);
+    }
+
+    Ok(())
 }
 
 // ----------------------------------------------------------------------------
@@ -122,24 +730,52 @@ pub async fn initialize_db() -> anyhow::Result<SqlitePool> {
 
 pub async fn add_account(pool: &SqlitePool, account: &Account) -> anyhow::Result<()> {
     // Account id assigned automatically
-    sqlx::query!(
-        "INSERT INTO accounts (name, username, password, url, description) 
-        VALUES (?1, ?2, ?3, ?4, ?5)",
+    with_retry(|| sqlx::query!(
+        "INSERT INTO accounts (name, username, password, url, description, encrypted_note, login_steps, encrypted_login_steps, change_password_url, category) 
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
         account.name,
         account.username,
         account.password,
         account.url,
-        account.description
+        account.description,
+        account.encrypted_note,
+        account.login_steps,
+        account.encrypted_login_steps,
+        account.change_password_url,
+        account.category
     )
-    .execute(pool)
+    .execute(pool))
     .await?; 
 
     Ok(())
 }
 
+/// Copies an account under a new name, returning the new account's ID
+/// 
+/// The encrypted password, note and login steps are copied as they are, they are under the same master password.
+/// Attachments are not copied
+pub async fn clone_account(pool: &SqlitePool, id: i64, new_name: &str) -> anyhow::Result<i64> {
+    let result = with_retry(|| sqlx::query!(
+        "INSERT INTO accounts (name, username, password, url, description, generation_policy, encrypted_note, expires_at, login_steps, encrypted_login_steps, change_password_url, category)
+        SELECT ?1, username, password, url, description, generation_policy, encrypted_note, expires_at, login_steps, encrypted_login_steps, change_password_url, category
+        FROM accounts WHERE id = ?2",
+        new_name,
+        id
+    )
+    .execute(pool))
+    .await?;
+
+    if result.rows_affected() == 0 {
+        anyhow::bail!("No account found with ID: {}", id);
+    }
+    Ok(result.last_insert_rowid())
+}
+
 pub async fn get_account_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<Account> {
     let account = sqlx::query_as!(Account,
-        "SELECT id, name, username, password, url, description
+        "SELECT id, name, username, password, url, description, encrypted_note as \"encrypted_note: bool\",
+            login_steps, encrypted_login_steps as \"encrypted_login_steps: bool\", change_password_url,
+            category as \"category: Category\"
         FROM accounts WHERE id = ?",
         id
     )
@@ -149,22 +785,68 @@ pub async fn get_account_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<Acc
     Ok(account)
 }
 
+/// Fetches an account that is about to be shown, counting the access when [`TRACK_ACCOUNT_ACCESS`] is set
+/// 
+/// The count is only a statistic, so failing to update it doesn't keep the account from being shown
+pub async fn retrieve_account(pool: &SqlitePool, id: i64) -> anyhow::Result<Account> {
+    let account = get_account_by_id(pool, id).await?;
+
+    if TRACK_ACCOUNT_ACCESS {
+        let counted = with_retry(|| sqlx::query!(
+            "UPDATE accounts SET access_count = access_count + 1 WHERE id = ?",
+            id
+        )
+        .execute(pool))
+        .await;
+        if let Err(err) = counted {
+            debug_log!("Failed to count the access to account {}: {}", id, err);
+        }
+    }
+
+    Ok(account)
+}
+
+/// Whether a lookup failed because nothing matched, rather than an ambiguous name or a database error
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::RowNotFound))
+}
+
+/// Looks up an account by name
+/// 
+/// With [`CASE_INSENSITIVE_NAMES`] "google" also finds "Google" (ASCII letters only). An exact match
+/// always wins, otherwise names that only differ in case are ambiguous and return an error
 pub async fn get_account_by_name(pool: &SqlitePool, name: &String) -> anyhow::Result<Account> {
-    let row = sqlx::query!(
-        "SELECT id, name, username, password, url, description
-        FROM accounts WHERE name = ?",
+    let rows = sqlx::query!(
+        "SELECT id, name, username, password, url, description, encrypted_note as \"encrypted_note: bool\",
+            login_steps, encrypted_login_steps as \"encrypted_login_steps: bool\", change_password_url,
+            category as \"category: Category\"
+        FROM accounts WHERE name = ? OR (? AND name = ? COLLATE NOCASE)
+        ORDER BY name = ? DESC, id",
+        name,
+        CASE_INSENSITIVE_NAMES,
+        name,
         name
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await?;
 
+    if rows.len() > 1 && rows[0].name != *name {
+        anyhow::bail!("More than one account matches {} when ignoring case, use the exact name or the ID", name);
+    }
+    let row = rows.into_iter().next().ok_or(sqlx::Error::RowNotFound)?;
+
     let account = Account {
-        id: row.id.expect("account.id was null"), // sqlx interprets id as Option
+        id: row.id,
         name: row.name,
         username: row.username,
         password: row.password,
         url: row.url,
         description: row.description,
+        encrypted_note: row.encrypted_note,
+        login_steps: row.login_steps,
+        encrypted_login_steps: row.encrypted_login_steps,
+        change_password_url: row.change_password_url,
+        category: row.category,
     };
 
     Ok(account)
@@ -174,11 +856,11 @@ pub async fn get_account_by_name(pool: &SqlitePool, name: &String) -> anyhow::Re
 pub async fn delete_account_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<()> {
     match get_account_by_id(pool, id).await {
         Ok(returned_account) => {
-            let query_result = sqlx::query!(
+            let query_result = with_retry(|| sqlx::query!(
                 "DELETE FROM accounts WHERE id = ?",
                 id
             )
-            .execute(pool)
+            .execute(pool))
             .await?;
 
             // Sanity check
@@ -197,14 +879,15 @@ pub async fn delete_account_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<
 }
 
 // TODO Make return account, and handle printing in user_interface.rs instead
+#[allow(dead_code)]
 pub async fn delete_account_by_name(pool: &SqlitePool, name: &String) -> anyhow::Result<()> {
     match get_account_by_name(pool, name).await {
         Ok(returned_account) => {
-            let query_result = sqlx::query!(
-                "DELETE FROM accounts WHERE name = ?",
-                name
+            let query_result = with_retry(|| sqlx::query!(
+                "DELETE FROM accounts WHERE id = ?",
+                returned_account.id
             )
-            .execute(pool)
+            .execute(pool))
             .await?;
 
             // Sanity check
@@ -226,35 +909,463 @@ pub async fn delete_account_by_name(pool: &SqlitePool, name: &String) -> anyhow:
 pub async fn list_accounts(pool: &SqlitePool) -> anyhow::Result<Vec<AccountSummary>> {
     // List all account ids, names, and descriptions from the database
     let summaries = sqlx::query_as!(AccountSummary,
-        "SELECT id, name, description FROM accounts"
+        "SELECT id, name,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\"
+        FROM accounts"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(summaries)
+}
+
+/// Every account summary grouped by [`Category`], ordered by name within each group
+pub async fn list_accounts_grouped(pool: &SqlitePool) -> anyhow::Result<BTreeMap<Category, Vec<AccountSummary>>> {
+    let rows = sqlx::query!(
+        "SELECT id as \"id!\", name,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\",
+            category as \"category: Category\"
+        FROM accounts ORDER BY name"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut groups: BTreeMap<Category, Vec<AccountSummary>> = BTreeMap::new();
+    for row in rows {
+        groups.entry(row.category).or_default().push(AccountSummary {
+            id: row.id,
+            name: row.name,
+            description: row.description,
+            encrypted_note: row.encrypted_note,
+        });
+    }
+
+    Ok(groups)
+}
+
+/// Accounts whose name is in the namespace `prefix`, including nested namespaces, ordered by name
+/// 
+/// `prefix` is matched case-sensitively, ie. "work" lists "work/github" and "work/team/github"
+pub async fn list_accounts_under(pool: &SqlitePool, prefix: &str) -> anyhow::Result<Vec<AccountSummary>> {
+    let prefix = format!("{}{}", prefix.trim_end_matches(NAMESPACE_SEPARATOR), NAMESPACE_SEPARATOR);
+    // substr instead of LIKE, so % and _ in names are not wildcards
+    let summaries = sqlx::query_as!(AccountSummary,
+        "SELECT id as \"id!\", name,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\"
+        FROM accounts WHERE substr(name, 1, length(?1)) = ?1 ORDER BY name",
+        prefix
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(summaries)
+}
+
+/// Account summaries with how often each was retrieved, most used first, see [`retrieve_account()`]
+pub async fn list_accounts_by_use(pool: &SqlitePool) -> anyhow::Result<Vec<(AccountSummary, i64)>> {
+    let rows = sqlx::query!(
+        "SELECT id as \"id!\", name,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\",
+            access_count
+        FROM accounts ORDER BY access_count DESC, name"
     )
     .fetch_all(pool)
     .await?;
 
+    let summaries = rows
+        .into_iter()
+        .map(|row| (
+            AccountSummary {
+                id: row.id,
+                name: row.name,
+                description: row.description,
+                encrypted_note: row.encrypted_note,
+            },
+            row.access_count,
+        ))
+        .collect();
+
     Ok(summaries)
 }
 
-pub async fn search_accounts_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<Vec<AccountSummary>>{
+/// One page of [`list_accounts()`], ordered by ID
+pub async fn list_accounts_paged(pool: &SqlitePool, limit: i64, offset: i64) -> anyhow::Result<Vec<AccountSummary>> {
+    let summaries = sqlx::query_as!(AccountSummary,
+        "SELECT id, name,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\"
+        FROM accounts ORDER BY id LIMIT ?1 OFFSET ?2",
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(summaries)
+}
+
+/// Number of stored accounts, whichever master encrypted them
+pub async fn count_accounts(pool: &SqlitePool) -> anyhow::Result<i64> {
+    let row = sqlx::query!(
+        "SELECT COUNT(*) AS total FROM accounts"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.total)
+}
+
+pub async fn list_account_overviews(pool: &SqlitePool) -> anyhow::Result<Vec<AccountOverview>> {
+    let overviews = sqlx::query_as!(AccountOverview,
+        "SELECT id, name, username, url FROM accounts ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(overviews)
+}
+
+/// Lists accounts that fail the data quality checks enabled in compile_config.rs
+/// 
+/// See [`REQUIRE_ACCOUNT_URL`], [`REQUIRE_ACCOUNT_DESCRIPTION`] and [`FLAG_MIXED_USERNAMES`]
+pub async fn list_incomplete_accounts(pool: &SqlitePool) -> anyhow::Result<Vec<AccountSummary>> {
+    let rows = sqlx::query!(
+        "SELECT id, name, username, url, description, encrypted_note as \"encrypted_note: bool\" FROM accounts WHERE NOT exclude_from_audit ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let is_blank = |value: &Option<String>| value.as_deref().is_none_or(|value| value.trim().is_empty());
+
+    // Local parts of every email style username, ie. "alice" for "alice@example.com"
+    let email_names: HashSet<String> = rows
+        .iter()
+        .filter_map(|row| row.username.split_once('@'))
+        .map(|(local, _)| local.to_lowercase())
+        .collect();
+    let handles: HashSet<String> = rows
+        .iter()
+        .filter(|row| !row.username.contains('@'))
+        .map(|row| row.username.to_lowercase())
+        .collect();
+
+    let summaries = rows
+        .into_iter()
+        .filter(|row| {
+            let mixed_username = match row.username.split_once('@') {
+                Some((local, _)) => handles.contains(&local.to_lowercase()),
+                None => email_names.contains(&row.username.to_lowercase()),
+            };
+
+            (REQUIRE_ACCOUNT_URL && is_blank(&row.url))
+                || (REQUIRE_ACCOUNT_DESCRIPTION && is_blank(&row.description))
+                || (FLAG_MIXED_USERNAMES && mixed_username)
+        })
+        .map(|row| AccountSummary {
+            id: row.id,
+            name: row.name,
+            // Encrypted notes are only decrypted when the full account is shown
+            description: if row.encrypted_note { None } else { row.description },
+            encrypted_note: row.encrypted_note,
+        })
+        .collect();
+
+    Ok(summaries)
+}
+
+/// Usernames and emails used by more than one account with how many use each, most used first
+/// 
+/// Compared ignoring case and surrounding spaces. Accounts excluded from audits aren't counted
+pub async fn username_usage_report(pool: &SqlitePool) -> anyhow::Result<Vec<(String, usize)>> {
+    let rows = sqlx::query!(
+        "SELECT lower(trim(username)) as \"username!: String\", count(*) as \"uses!: i64\"
+        FROM accounts WHERE NOT exclude_from_audit AND trim(username) != ''
+        GROUP BY lower(trim(username)) HAVING count(*) > 1
+        ORDER BY count(*) DESC, lower(trim(username))"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.username, row.uses as usize)).collect())
+}
+
+/// Marks an account as not a real password (ie. an API key), so audits skip it
+pub async fn set_audit_exclusion(pool: &SqlitePool, id: i64, excluded: bool) -> anyhow::Result<()> {
+    let query_result = with_retry(|| sqlx::query!(
+        "UPDATE accounts SET exclude_from_audit = ? WHERE id = ?",
+        excluded,
+        id
+    )
+    .execute(pool))
+    .await?;
+
+    if query_result.rows_affected() == 0 {
+        return Err(anyhow::anyhow!("UPDATE failed: No account found with ID: {}", id))
+    }
+
+    Ok(())
+}
+
+pub async fn is_excluded_from_audit(pool: &SqlitePool, id: i64) -> anyhow::Result<bool> {
+    let row = sqlx::query!(
+        "SELECT exclude_from_audit as \"exclude_from_audit: bool\" FROM accounts WHERE id = ?",
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.exclude_from_audit)
+}
+
+/// IDs of every account excluded with [`set_audit_exclusion()`]
+pub async fn list_audit_excluded(pool: &SqlitePool) -> anyhow::Result<HashSet<i64>> {
+    let ids = sqlx::query_scalar!("SELECT id FROM accounts WHERE exclude_from_audit")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(ids.into_iter().collect())
+}
+
+/// Stores the account's password generation policy as JSON, None clears it
+pub async fn set_generation_policy(pool: &SqlitePool, id: i64, policy: Option<&PasswordOptions>) -> anyhow::Result<()> {
+    let policy_json = policy.map(serde_json::to_string).transpose()?;
+
+    let query_result = with_retry(|| sqlx::query!(
+        "UPDATE accounts SET generation_policy = ? WHERE id = ?",
+        policy_json,
+        id
+    )
+    .execute(pool))
+    .await?;
+
+    if query_result.rows_affected() == 0 {
+        return Err(anyhow::anyhow!("UPDATE failed: No account found with ID: {}", id))
+    }
+
+    Ok(())
+}
+
+/// Returns the account's password generation policy, if one was set
+pub async fn get_generation_policy(pool: &SqlitePool, id: i64) -> anyhow::Result<Option<PasswordOptions>> {
+    let row = sqlx::query!(
+        "SELECT generation_policy FROM accounts WHERE id = ?",
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let policy = row.generation_policy.as_deref().map(serde_json::from_str).transpose()?;
+    Ok(policy)
+}
+
+/// Sets the date an account's credentials should be reviewed or rotated by, None clears it
+pub async fn set_expiry(pool: &SqlitePool, id: i64, date: Option<NaiveDate>) -> anyhow::Result<()> {
+    let query_result = with_retry(|| sqlx::query!(
+        "UPDATE accounts SET expires_at = ? WHERE id = ?",
+        date,
+        id
+    )
+    .execute(pool))
+    .await?;
+
+    if query_result.rows_affected() == 0 {
+        return Err(anyhow::anyhow!("UPDATE failed: No account found with ID: {}", id))
+    }
+
+    Ok(())
+}
+
+pub async fn get_expiry(pool: &SqlitePool, id: i64) -> anyhow::Result<Option<NaiveDate>> {
+    let row = sqlx::query!(
+        "SELECT expires_at as \"expires_at: NaiveDate\" FROM accounts WHERE id = ?",
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.expires_at)
+}
+
+/// Lists accounts whose review date is today or has passed, oldest first, with their change-password page if set
+pub async fn list_expired(pool: &SqlitePool) -> anyhow::Result<Vec<(AccountSummary, NaiveDate, Option<String>)>> {
+    let today = Local::now().date_naive();
+    let rows = sqlx::query!(
+        "SELECT id, name,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\",
+            expires_at as \"expires_at!: NaiveDate\",
+            change_password_url
+        FROM accounts WHERE expires_at <= ? ORDER BY expires_at",
+        today
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let expired = rows
+        .into_iter()
+        .map(|row| {
+            let summary = AccountSummary {
+                id: row.id,
+                name: row.name,
+                description: row.description,
+                encrypted_note: row.encrypted_note,
+            };
+            (summary, row.expires_at, row.change_password_url)
+        })
+        .collect();
+
+    Ok(expired)
+}
+
+/// Lists the accounts whose stored password decrypts to `password`
+/// 
+/// Every password is decrypted to compare, so this takes a moment on large vaults.
+/// Entries encrypted by another master can't be compared and are skipped
+pub async fn find_accounts_using_password(pool: &SqlitePool, master_password: &String, password: &str) -> anyhow::Result<Vec<AccountSummary>> {
+    let rows = sqlx::query!(
+        "SELECT id, name, password,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\"
+        FROM accounts ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut matches = Vec::new();
+    for row in rows {
+        let Ok(mut stored_password) = try_decrypt_password(master_password, &row.password) else {
+            continue;
+        };
+        let same = bool::from(stored_password.as_bytes().ct_eq(password.as_bytes()));
+        stored_password.zeroize();
+
+        if same {
+            matches.push(AccountSummary {
+                id: row.id,
+                name: row.name,
+                description: row.description,
+                encrypted_note: row.encrypted_note,
+            });
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Decrypted passwords shorter than this aren't searched for, they turn up in unrelated bytes by chance
+const PLAINTEXT_SCAN_MIN_LENGTH: usize = 6;
+
+/// Looks for passwords that ended up unencrypted in the database, ie. after a bug skipped encryption
+/// 
+/// An account is suspicious when its password column doesn't look like ciphertext at all,
+/// or when its decrypted password appears anywhere in the raw database or -wal file bytes
+/// (this also catches copies left in free pages or other columns).
+/// Entries that look encrypted but belong to another master are skipped
+/// 
+/// # Returns
+/// 
+/// Returns the IDs of the suspicious accounts
+pub async fn scan_for_plaintext(pool: &SqlitePool, master_password: &String) -> anyhow::Result<Vec<i64>> {
+    let db_path = db_file_path(pool).await?;
+    let mut raw = fs::read(&db_path)
+        .with_context(|| format!("Failed to read {}", db_path.display()))?;
+    // Pages that aren't checkpointed into the main file yet live in the WAL
+    let mut wal_path = db_path.into_os_string();
+    wal_path.push("-wal");
+    if let Ok(mut wal) = fs::read(&wal_path) {
+        raw.extend_from_slice(&wal);
+        wal.zeroize();
+    }
+
+    let rows = sqlx::query!("SELECT id, password FROM accounts ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+
+    let mut suspicious = Vec::new();
+    for row in rows {
+        match try_decrypt_password(master_password, &row.password) {
+            Ok(mut password) => {
+                let needle = password.as_bytes();
+                if needle.len() >= PLAINTEXT_SCAN_MIN_LENGTH && raw.windows(needle.len()).any(|window| window == needle) {
+                    suspicious.push(row.id);
+                }
+                password.zeroize();
+            }
+            Err(_) if !looks_like_ciphertext(&row.password) => suspicious.push(row.id),
+            Err(_) => {}
+        }
+    }
+    raw.zeroize();
+
+    Ok(suspicious)
+}
+
+/// Finds accounts whose decrypted password contains `substring`
+/// 
+/// Every password is decrypted in memory to check it, each one is zeroized right after
+pub async fn search_by_password_content(pool: &SqlitePool, master_password: &String, substring: &str) -> anyhow::Result<Vec<AccountSummary>> {
+    let rows = sqlx::query!(
+        "SELECT id, name, password,
+            CASE WHEN encrypted_note THEN NULL ELSE description END as \"description?\",
+            encrypted_note as \"encrypted_note: bool\"
+        FROM accounts ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut matches = Vec::new();
+    for row in rows {
+        let Ok(mut stored_password) = try_decrypt_password(master_password, &row.password) else {
+            continue;
+        };
+        let found = stored_password.contains(substring);
+        stored_password.zeroize();
+
+        if found {
+            matches.push(AccountSummary {
+                id: row.id,
+                name: row.name,
+                description: row.description,
+                encrypted_note: row.encrypted_note,
+            });
+        }
+    }
+
+    Ok(matches)
+}
+
+#[allow(dead_code)]
+pub async fn search_accounts_by_id(_pool: &SqlitePool, _id: i64) -> anyhow::Result<Vec<AccountSummary>>{
     unimplemented!()
 }
 
-pub async fn search_accounts_by_name(pool: &SqlitePool, name: &String) -> anyhow::Result<Vec<AccountSummary>>{
+#[allow(dead_code)]
+pub async fn search_accounts_by_name(_pool: &SqlitePool, _name: &str) -> anyhow::Result<Vec<AccountSummary>>{
     unimplemented!()
 }
 
 pub async fn update_account(pool: &SqlitePool, account: &Account) -> anyhow::Result<()> {
-    let query_result = sqlx::query!(
+    let query_result = with_retry(|| sqlx::query!(
         "UPDATE accounts 
-        SET name = ?, username = ?, password = ?, url = ?, description = ? 
+        SET name = ?, username = ?, password = ?, url = ?, description = ?, encrypted_note = ?, login_steps = ?, encrypted_login_steps = ?, change_password_url = ?, category = ? 
         WHERE id = ?",
         account.name,
         account.username,
         account.password,
         account.url,
         account.description,
+        account.encrypted_note,
+        account.login_steps,
+        account.encrypted_login_steps,
+        account.change_password_url,
+        account.category,
         account.id
     )
-    .execute(pool)
+    .execute(pool))
     .await?; 
 
     if query_result.rows_affected() == 0 {
@@ -265,24 +1376,82 @@ pub async fn update_account(pool: &SqlitePool, account: &Account) -> anyhow::Res
 }
 
 
+// ----------------------------------------------------------------------------
+// Attachments ----------------------------------------------------------------
+
+/// Stores an encrypted attachment, `size` is the size of the original file
+pub async fn add_attachment(pool: &SqlitePool, attachment: &Attachment, size: i64) -> anyhow::Result<()> {
+    // Attachment id assigned automatically
+    with_retry(|| sqlx::query!(
+        "INSERT INTO attachments (account_id, filename, size, data) 
+        VALUES (?, ?, ?, ?)",
+        attachment.account_id,
+        attachment.filename,
+        size,
+        attachment.data
+    )
+    .execute(pool))
+    .await?; 
+
+    Ok(())
+}
+
+pub async fn list_attachments(pool: &SqlitePool, account_id: i64) -> anyhow::Result<Vec<AttachmentSummary>> {
+    let summaries = sqlx::query_as!(AttachmentSummary,
+        "SELECT id, filename, size FROM attachments WHERE account_id = ? ORDER BY id",
+        account_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(summaries)
+}
+
+pub async fn get_attachment_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<Attachment> {
+    let attachment = sqlx::query_as!(Attachment,
+        "SELECT id, account_id, filename, data FROM attachments WHERE id = ?",
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(attachment)
+}
+
+pub async fn delete_attachment_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<()> {
+    let query_result = with_retry(|| sqlx::query!(
+        "DELETE FROM attachments WHERE id = ?",
+        id
+    )
+    .execute(pool))
+    .await?;
+
+    if query_result.rows_affected() == 0 {
+        return Err(anyhow::anyhow!("DELETE failed: No attachment found with ID: {}", id))
+    }
+
+    Ok(())
+}
+
 // ----------------------------------------------------------------------------
 // Masters --------------------------------------------------------------------
 pub async fn add_master(pool: &SqlitePool, master: &Master) -> anyhow::Result<()> {
     // Master id assigned automatically
-    sqlx::query!(
-        "INSERT INTO masters (username, password) 
-        VALUES (?, ?)",
+    with_retry(|| sqlx::query!(
+        "INSERT INTO masters (username, password, keyfile_required) 
+        VALUES (?, ?, ?)",
         master.username,
-        master.password
+        master.password,
+        master.keyfile_required
     )
-    .execute(pool)
+    .execute(pool))
     .await?; 
 
     Ok(())
 }
 pub async fn get_master_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<Master> {
     let master = sqlx::query_as!(Master,
-        "SELECT id, username, password
+        "SELECT id, username, password, keyfile_required as \"keyfile_required: bool\"
         FROM masters WHERE id = ?",
         id
     )
@@ -292,9 +1461,21 @@ pub async fn get_master_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<Mast
     Ok(master)
 }
 
+/// Returns the oldest master, which is the only one in single master mode
+pub async fn get_first_master(pool: &SqlitePool) -> anyhow::Result<Master> {
+    let master = sqlx::query_as!(Master,
+        "SELECT id, username, password, keyfile_required as \"keyfile_required: bool\"
+        FROM masters ORDER BY id LIMIT 1"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(master)
+}
+
 pub async fn get_master_by_username(pool: &SqlitePool, username: &String) -> anyhow::Result<Master> {
     let master = sqlx::query_as!(Master,
-        "SELECT id, username, password
+        "SELECT id, username, password, keyfile_required as \"keyfile_required: bool\"
         FROM masters WHERE username = ?",
         username
     )
@@ -304,14 +1485,15 @@ pub async fn get_master_by_username(pool: &SqlitePool, username: &String) -> any
     Ok(master)
 }
 
+#[allow(dead_code)]
 pub async fn delete_master_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<()> {
     match get_master_by_id(pool, id).await {
         Ok(returned_master) => {
-            let query_result = sqlx::query!(
+            let query_result = with_retry(|| sqlx::query!(
                 "DELETE FROM masters WHERE id = ?",
                 id
             )
-            .execute(pool)
+            .execute(pool))
             .await?;
 
             // Sanity check
@@ -329,14 +1511,15 @@ pub async fn delete_master_by_id(pool: &SqlitePool, id: i64) -> anyhow::Result<(
     }
 }
 
+#[allow(dead_code)]
 pub async fn delete_master_by_username(pool: &SqlitePool, username: &String) -> anyhow::Result<()> {
     match get_master_by_username(pool, username).await {
         Ok(returned_master) => {
-            let query_result = sqlx::query!(
+            let query_result = with_retry(|| sqlx::query!(
                 "DELETE FROM masters WHERE username = ?",
                 username
             )
-            .execute(pool)
+            .execute(pool))
             .await?;
 
             // Sanity check
@@ -355,9 +1538,10 @@ pub async fn delete_master_by_username(pool: &SqlitePool, username: &String) ->
 }
 
 // TODO Don't return password? Maybe make another struct
+#[allow(dead_code)]
 pub async fn list_master_accounts(pool: &SqlitePool) -> anyhow::Result<Vec<Master>> {
     let summaries = sqlx::query_as!(Master,
-        "SELECT id, username, password FROM masters"
+        "SELECT id, username, password, keyfile_required as \"keyfile_required: bool\" FROM masters"
     )
     .fetch_all(pool)
     .await?;
@@ -367,15 +1551,16 @@ pub async fn list_master_accounts(pool: &SqlitePool) -> anyhow::Result<Vec<Maste
 
 
 pub async fn update_master(pool: &SqlitePool, master: &Master) -> anyhow::Result<()> {
-    let query_result = sqlx::query!(
+    let query_result = with_retry(|| sqlx::query!(
         "UPDATE masters 
-        SET username = ?, password = ?
+        SET username = ?, password = ?, keyfile_required = ?
         WHERE id = ?",
         master.username,
         master.password,
+        master.keyfile_required,
         master.id
     )
-    .execute(pool)
+    .execute(pool))
     .await?; 
 
     if query_result.rows_affected() == 0 {
@@ -385,12 +1570,318 @@ pub async fn update_master(pool: &SqlitePool, master: &Master) -> anyhow::Result
     Ok(())
 }
 
+/// Changes a master's password and re-encrypts every stored password, encrypted note, encrypted login steps and attachment
+/// from the old master password to the new one, in a single transaction
+/// 
+/// Both passwords are the full secrets used for key derivation, ie. combined with the key file if required.
+/// Entries that don't decrypt with the old password were added by another master and are left as they are
+/// 
+/// # Returns
+/// 
+/// Returns the number of entries that were left as they are
+pub async fn change_master_password(pool: &SqlitePool, master: &Master, old_password: &String, new_password: &String) -> anyhow::Result<usize> {
+    let mut tx = pool.begin().await?;
+    let mut skipped = 0;
+
+    let accounts = sqlx::query!("SELECT id, password FROM accounts")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in accounts {
+        let Ok(mut password) = try_decrypt_password(old_password, &row.password) else {
+            skipped += 1;
+            continue;
+        };
+        let encrypted_password = encrypt_password(new_password, &password);
+        password.zeroize();
+
+        sqlx::query!(
+            "UPDATE accounts SET password = ? WHERE id = ?",
+            encrypted_password,
+            row.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let notes = sqlx::query!("SELECT id, description FROM accounts WHERE encrypted_note AND description IS NOT NULL")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in notes {
+        let Some(Ok(mut description)) = row.description.as_deref().map(|note| try_decrypt_password(old_password, note)) else {
+            skipped += 1;
+            continue;
+        };
+        let encrypted_description = encrypt_password(new_password, &description);
+        description.zeroize();
+
+        sqlx::query!(
+            "UPDATE accounts SET description = ? WHERE id = ?",
+            encrypted_description,
+            row.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let login_steps = sqlx::query!("SELECT id, login_steps FROM accounts WHERE encrypted_login_steps AND login_steps IS NOT NULL")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in login_steps {
+        let Some(Ok(mut steps)) = row.login_steps.as_deref().map(|steps| try_decrypt_password(old_password, steps)) else {
+            skipped += 1;
+            continue;
+        };
+        let encrypted_steps = encrypt_password(new_password, &steps);
+        steps.zeroize();
+
+        sqlx::query!(
+            "UPDATE accounts SET login_steps = ? WHERE id = ?",
+            encrypted_steps,
+            row.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let attachments = sqlx::query!("SELECT id, data FROM attachments")
+        .fetch_all(&mut *tx)
+        .await?;
+    for row in attachments {
+        let Ok(mut data) = decrypt_bytes(old_password, &row.data) else {
+            skipped += 1;
+            continue;
+        };
+        let encrypted_data = encrypt_bytes(new_password, &data);
+        data.zeroize();
+
+        sqlx::query!(
+            "UPDATE attachments SET data = ? WHERE id = ?",
+            encrypted_data,
+            row.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let query_result = sqlx::query!(
+        "UPDATE masters 
+        SET username = ?, password = ?, keyfile_required = ?
+        WHERE id = ?",
+        master.username,
+        master.password,
+        master.keyfile_required,
+        master.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if query_result.rows_affected() == 0 {
+        return Err(anyhow::anyhow!("UPDATE failed: Query returned no rows"))
+    }
+
+    // Dropping the transaction on an error rolls it back, entries never end up under a mix of passwords
+    tx.commit().await?;
+
+    Ok(skipped)
+}
+
+/// Argon2 hash (default parameters) of a password no master has, verified against for unknown usernames
+const UNKNOWN_MASTER_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$HV2q58/houEfuy/reKUIHw$8/d+6mI5QtaSbOQYXoiPwvw9kvBydX3BiosxDDB38Ds";
+
+/// Checks a master's credentials
+/// 
+/// An unknown username is a wrong credential like a wrong password and returns `Ok(false)`,
+/// an error means the database itself couldn't be read.
+/// The password is still hashed for an unknown username, so the response time doesn't tell whether it exists
 pub async fn verify_master(pool: &SqlitePool, username: &String, password: &String) -> anyhow::Result<bool> {
-    let stored_master = get_master_by_username(pool, username).await?;
+    let stored_master = match get_master_by_username(pool, username).await {
+        Ok(master) => master,
+        Err(err) if is_not_found(&err) => {
+            verify_master_password(UNKNOWN_MASTER_HASH, password);
+            return Ok(false);
+        }
+        Err(err) => return Err(err),
+    };
 
-    if verify_master_password(&stored_master.password, &password){
+    if verify_master_password(&stored_master.password, password){
         Ok(true)
     } else {
         Ok(false)
     }
-}
\ No newline at end of file
+}
+
+/// Checks whether any master account has been set up
+pub async fn master_exists(pool: &SqlitePool) -> anyhow::Result<bool> {
+    let row = sqlx::query!(
+        "SELECT EXISTS(SELECT 1 FROM masters) AS present"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.present != 0)
+}
+
+/// Master password seeded by older versions before the setup flow existed
+const LEGACY_DEFAULT_MASTER_PASSWORD: &str = "changethis";
+
+/// Checks whether any master still uses the old default password or an empty one
+pub async fn is_default_master(pool: &SqlitePool) -> anyhow::Result<bool> {
+    let rows = sqlx::query!("SELECT password FROM masters")
+        .fetch_all(pool)
+        .await?;
+
+    let weak_passwords = [LEGACY_DEFAULT_MASTER_PASSWORD.to_string(), String::new()];
+    let found = rows.iter().any(|row| {
+        weak_passwords.iter().any(|weak_password| verify_master_password(&row.password, weak_password))
+    });
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::hash_master_password;
+
+    async fn add_named_account(pool: &SqlitePool, name: &str) {
+        let account = Account::new(name.to_string(), "user".to_string(), "ciphertext".to_string(), None, None);
+        add_account(pool, &account).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn name_in_different_case_is_found() {
+        let pool = initialize_memory_db().await.unwrap();
+        add_named_account(&pool, "GitHub").await;
+
+        let account = get_account_by_name(&pool, &"github".to_string()).await.unwrap();
+        assert_eq!(account.name, "GitHub");
+    }
+
+    #[tokio::test]
+    async fn exact_name_wins_over_case_variant() {
+        let pool = initialize_memory_db().await.unwrap();
+        add_named_account(&pool, "GitHub").await;
+        add_named_account(&pool, "github").await;
+
+        let account = get_account_by_name(&pool, &"github".to_string()).await.unwrap();
+        assert_eq!(account.name, "github");
+    }
+
+    #[tokio::test]
+    async fn case_variants_without_exact_match_are_ambiguous() {
+        let pool = initialize_memory_db().await.unwrap();
+        add_named_account(&pool, "GitHub").await;
+        add_named_account(&pool, "GITHUB").await;
+
+        let err = get_account_by_name(&pool, &"github".to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("More than one account matches"));
+        assert!(!is_not_found(&err));
+    }
+
+    #[tokio::test]
+    async fn retrieving_an_account_counts_the_access() {
+        let pool = initialize_memory_db().await.unwrap();
+        add_named_account(&pool, "GitHub").await;
+        let id = get_account_by_name(&pool, &"GitHub".to_string()).await.unwrap().id;
+
+        retrieve_account(&pool, id).await.unwrap();
+        retrieve_account(&pool, id).await.unwrap();
+
+        let (summary, access_count) = list_accounts_by_use(&pool).await.unwrap().remove(0);
+        assert_eq!(summary.name, "GitHub");
+        assert_eq!(access_count, if TRACK_ACCOUNT_ACCESS { 2 } else { 0 });
+    }
+
+    async fn add_test_master(pool: &SqlitePool, username: &str, password: &str) {
+        let hash = hash_master_password(&password.to_string()).unwrap();
+        add_master(pool, &Master::new(username.to_string(), hash)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unknown_master_username_is_not_an_error() {
+        let pool = initialize_memory_db().await.unwrap();
+        add_test_master(&pool, "alice", "correct-password").await;
+
+        let verified = verify_master(&pool, &"bob".to_string(), &"correct-password".to_string()).await.unwrap();
+        assert!(!verified);
+    }
+
+    #[tokio::test]
+    async fn wrong_master_password_is_not_an_error() {
+        let pool = initialize_memory_db().await.unwrap();
+        add_test_master(&pool, "alice", "correct-password").await;
+
+        assert!(!verify_master(&pool, &"alice".to_string(), &"wrong-password".to_string()).await.unwrap());
+        assert!(verify_master(&pool, &"alice".to_string(), &"correct-password".to_string()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unreadable_masters_table_is_an_error() {
+        let pool = initialize_memory_db().await.unwrap();
+        sqlx::query("DROP TABLE masters").execute(&pool).await.unwrap();
+
+        assert!(verify_master(&pool, &"alice".to_string(), &"correct-password".to_string()).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prepare_db_file_creates_private_directories_and_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("password-manager-test-{}", std::process::id()));
+        let db_path = root.join("nested").join("vault").join("passwords.db");
+
+        let result = prepare_db_file(&db_path);
+        let mode = |path: &Path| fs::metadata(path).map(|metadata| metadata.permissions().mode() & 0o777);
+        let modes = (mode(&root), mode(&root.join("nested")), mode(db_path.parent().unwrap()), mode(&db_path));
+        let _ = fs::remove_dir_all(&root);
+
+        result.unwrap();
+        assert_eq!(modes.0.unwrap(), 0o700);
+        assert_eq!(modes.1.unwrap(), 0o700);
+        assert_eq!(modes.2.unwrap(), 0o700);
+        assert_eq!(modes.3.unwrap(), 0o600);
+    }
+
+    #[tokio::test]
+    async fn change_master_password_reencrypts_own_entries_only() {
+        let pool = initialize_memory_db().await.unwrap();
+        let old_password = "old-master-password".to_string();
+        let new_password = "new-master-password".to_string();
+        let other_password = "other-master-password".to_string();
+        add_test_master(&pool, "alice", &old_password).await;
+
+        let mut own = Account::new(
+            "own".to_string(),
+            "alice".to_string(),
+            encrypt_password(&old_password, &"account-secret".to_string()),
+            None,
+            Some(encrypt_password(&old_password, &"note-secret".to_string())),
+        );
+        own.encrypted_note = true;
+        add_account(&pool, &own).await.unwrap();
+        let own = get_account_by_name(&pool, &"own".to_string()).await.unwrap();
+        let attachment = Attachment::new(own.id, "recovery.txt".to_string(), encrypt_bytes(&old_password, b"attachment-secret"));
+        add_attachment(&pool, &attachment, 17).await.unwrap();
+        let attachment_id = list_attachments(&pool, own.id).await.unwrap()[0].id;
+
+        let other_ciphertext = encrypt_password(&other_password, &"other-secret".to_string());
+        let other = Account::new("other".to_string(), "bob".to_string(), other_ciphertext.clone(), None, None);
+        add_account(&pool, &other).await.unwrap();
+
+        let mut master = get_master_by_username(&pool, &"alice".to_string()).await.unwrap();
+        master.password = hash_master_password(&new_password).unwrap();
+        let skipped = change_master_password(&pool, &master, &old_password, &new_password).await.unwrap();
+        assert_eq!(skipped, 1);
+
+        let own = get_account_by_name(&pool, &"own".to_string()).await.unwrap();
+        assert_eq!(try_decrypt_password(&new_password, &own.password).unwrap(), "account-secret");
+        assert_eq!(try_decrypt_password(&new_password, own.description.as_ref().unwrap()).unwrap(), "note-secret");
+        let attachment = get_attachment_by_id(&pool, attachment_id).await.unwrap();
+        assert_eq!(decrypt_bytes(&new_password, &attachment.data).unwrap(), b"attachment-secret");
+
+        let other = get_account_by_name(&pool, &"other".to_string()).await.unwrap();
+        assert_eq!(other.password, other_ciphertext);
+        assert!(verify_master(&pool, &"alice".to_string(), &new_password).await.unwrap());
+    }
+}
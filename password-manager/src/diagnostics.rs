@@ -0,0 +1,25 @@
+use std::{env, sync::OnceLock};
+
+use crate::compile_config::DEBUG_FLAG;
+
+/// Environment variable that turns on verbose diagnostics without rebuilding
+const DEBUG_ENV_VAR: &str = "PM_DEBUG";
+
+/// Whether verbose diagnostics are on, through [`DEBUG_FLAG`] or `PM_DEBUG=1`
+pub fn verbose_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| DEBUG_FLAG || env::var(DEBUG_ENV_VAR).is_ok_and(|value| value == "1"))
+}
+
+/// Prints a diagnostic to stderr when verbose diagnostics are on
+/// 
+/// Never pass it passwords, keys or decrypted data
+macro_rules! debug_log {
+    ($($arg:tt)*) => {
+        if $crate::diagnostics::verbose_enabled() {
+            eprintln!("[debug] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use debug_log;
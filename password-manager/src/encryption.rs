@@ -1,9 +1,14 @@
-use anyhow::Result;
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
 use argon2::{
     password_hash::{PasswordHasher, SaltString}, Argon2, PasswordHash, PasswordVerifier
 };
 use base64::{engine::general_purpose::URL_SAFE, Engine as _};
 use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+use zeroize::Zeroize;
 
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit},
@@ -27,7 +32,9 @@ pub fn hash_master_password(password: &String) -> Result<String> {
 
 
 /// Verifies if input password matches the hashed master password
-pub fn verify_master_password(stored_b64_hash: &String, password: &String) -> bool {
+/// 
+/// The argon2 verifier compares the computed and stored hash outputs in constant time
+pub fn verify_master_password(stored_b64_hash: &str, password: &String) -> bool {
     let argon2 = Argon2::default();
 
     match PasswordHash::new(stored_b64_hash) {
@@ -38,8 +45,92 @@ pub fn verify_master_password(stored_b64_hash: &String, password: &String) -> bo
     }
 }
 
+/// Brings a master password into Unicode NFC form
+/// 
+/// The same password can arrive precomposed (`é`) or decomposed (`e` + `\u{301}`) depending
+/// on the keyboard or input method, normalizing it before hashing and key derivation lets both log in
+pub fn normalize_master_password(password: &str) -> String {
+    password.nfc().collect()
+}
+
+/// The forms a typed master password is tried in, NFC first
+/// 
+/// Masters set up before passwords were normalized are hashed, and their SQLCipher file keyed,
+/// from the form as typed, so that form is tried second when it differs
+pub fn master_password_candidates(password: &str) -> Vec<String> {
+    let normalized = normalize_master_password(password);
+    if normalized == password {
+        vec![normalized]
+    } else {
+        vec![normalized, password.to_string()]
+    }
+}
+
+/// SHA-256 digest of a key file's contents
+pub struct KeyFileDigest([u8; 32]);
+
+impl Drop for KeyFileDigest {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Reads a key file and hashes its contents, the contents can be any bytes
+pub fn read_keyfile(path: &Path) -> Result<KeyFileDigest> {
+    let mut contents = fs::read(path)
+        .with_context(|| format!("Failed to read key file {}", path.display()))?;
+    if contents.is_empty() {
+        anyhow::bail!("Key file {} is empty", path.display());
+    }
+
+    let digest = KeyFileDigest(Sha256::digest(&contents).into());
+    contents.zeroize();
+
+    Ok(digest)
+}
+
+/// Combines the master password with a key file digest
+/// 
+/// The result replaces the master password for hashing and key derivation,
+/// so neither the password nor the key file is enough on its own
+pub fn combine_with_keyfile(master_password: &str, keyfile: &KeyFileDigest) -> String {
+    format!("{}{}", master_password, URL_SAFE.encode(keyfile.0))
+}
+
 const AES_KEY_SIZE: usize = 32;  // 256-bit key size for AES-256
 
+/// Appended to the master hash's salt, so the fingerprint is never the stored hash itself
+const FINGERPRINT_CONTEXT: &str = "key-fingerprint";
+
+/// Short fingerprint of the master password (or password and key file), safe to show on screen
+/// 
+/// Derived with Argon2 like the account keys and then hashed, so guessing the password
+/// from it costs as much as from the stored master hash. The salt comes from that master's
+/// stored hash, so the same password gives a different fingerprint in every vault
+/// 
+/// # Returns
+/// 
+/// Returns the first 8 bytes as hex, ie. "3f2a-9c41-07be-d512", or an error if the stored hash can't be parsed
+pub fn key_fingerprint(master_password: &String, stored_master_hash: &str) -> Result<String> {
+    let parsed_hash = PasswordHash::new(stored_master_hash)
+        .map_err(|e| anyhow::anyhow!("Stored master hash is invalid: {}", e))?;
+    let salt = parsed_hash.salt.context("Stored master hash has no salt")?;
+    let salt = format!("{}{}", salt.as_str(), FINGERPRINT_CONTEXT);
+
+    let mut key = [0u8; AES_KEY_SIZE];
+    Argon2::default()
+        .hash_password_into(master_password.as_bytes(), salt.as_bytes(), &mut key)
+        .map_err(|e| anyhow::anyhow!("Password hashing failed: {}", e))?;
+    let digest = Sha256::digest(key);
+    key.zeroize();
+
+    Ok(digest[..8]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join("-"))
+}
+
 /// Generates an AES key using password and random salt
 /// 
 /// This function assumes correct master password input
@@ -71,6 +162,49 @@ fn derive_aes_key_from_master_password_and_salt(master_password: &String, salt:
 }
 
 
+/// Encrypt arbitrary bytes using AES-GCM
+/// 
+/// Same scheme as [`encrypt_password()`], used for attachments which are stored as BLOBs
+/// 
+/// # Returns
+/// 
+/// Returns the raw bytes "nonce + encrypted_data + salt"
+pub fn encrypt_bytes(master_password: &String, data: &[u8]) -> Vec<u8> {
+    let (mut key, salt) = create_aes_key_from_master_password(master_password);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    key.zeroize();
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher.encrypt(&nonce, data).expect("Failed encrypting data");
+
+    let mut encrypted_data = nonce.to_vec();
+    encrypted_data.extend_from_slice(&ciphertext);
+    encrypted_data.extend_from_slice(salt.as_bytes());
+
+    encrypted_data
+}
+
+/// Decrypt bytes produced by [`encrypt_bytes()`]
+/// 
+/// Unlike [`decrypt_password()`] this returns an error instead of panicking,
+/// the data may come from an older master password
+pub fn decrypt_bytes(master_password: &String, encrypted_data: &[u8]) -> Result<Vec<u8>> {
+    // Nonce is the first 12 bytes, salt is the last 22 bytes
+    if encrypted_data.len() < 12 + 22 {
+        anyhow::bail!("Encrypted data is too short");
+    }
+    let (remaining_data, salt) = encrypted_data.split_at(encrypted_data.len() - 22);
+    let salt = std::str::from_utf8(salt).context("Encrypted data has an invalid salt")?;
+    let (nonce, ciphertext) = remaining_data.split_at(12);
+
+    let mut key = derive_aes_key_from_master_password_and_salt(master_password, salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    key.zeroize();
+
+    cipher.decrypt(nonce.into(), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Decryption failed, the data was encrypted with a different master password or is corrupted"))
+}
+
 /// Encrypt the password using AES-GCM
 /// 
 /// # Arguments
@@ -87,7 +221,7 @@ pub fn encrypt_password(master_password: &String, password: &String) -> String {
     let (key, salt) = create_aes_key_from_master_password(master_password);
     let key = Key::<Aes256Gcm>::from_slice(&key);
 
-    let cipher = Aes256Gcm::new(&key);
+    let cipher = Aes256Gcm::new(key);
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
 
     let ciphertext = cipher.encrypt(&nonce, password.as_bytes()).expect("Failed encrypting password");
@@ -100,9 +234,27 @@ pub fn encrypt_password(master_password: &String, password: &String) -> String {
     encrypted_data.extend_from_slice(salt.as_bytes());
 
     // Convert to base64 string
-    let encrypted_data_string = URL_SAFE.encode(encrypted_data);
+    URL_SAFE.encode(encrypted_data)
+}
 
-    encrypted_data_string
+/// Checks whether a stored value has the shape [`encrypt_password()`] produces, without decrypting it
+/// 
+/// Base64 of at least a nonce, an AES-GCM tag and the 22 byte salt, the salt itself being base64
+pub fn looks_like_ciphertext(value: &str) -> bool {
+    match URL_SAFE.decode(value) {
+        Ok(data) if data.len() >= 12 + 16 + 22 => data[data.len() - 22..].iter().all(u8::is_ascii_alphanumeric),
+        _ => false,
+    }
+}
+
+/// Decrypt a password produced by [`encrypt_password()`], returning an error instead of panicking
+/// 
+/// Used to probe values that may not be valid ciphertext, see the `repair` command
+pub fn try_decrypt_password(master_password: &String, encrypted_data_string: &str) -> Result<String> {
+    let encrypted_data = URL_SAFE.decode(encrypted_data_string).context("Password is not valid base64")?;
+    let decrypted_data = decrypt_bytes(master_password, &encrypted_data)?;
+
+    String::from_utf8(decrypted_data).context("Decrypted password is not valid UTF-8")
 }
 
 /// Decrypt the password using AES-GCM
@@ -131,17 +283,53 @@ pub fn decrypt_password(master_password: &String, encrypted_data_string: &String
     let key = derive_aes_key_from_master_password_and_salt(master_password, salt);
     let key = Key::<Aes256Gcm>::from_slice(&key);
 
-    let cipher = Aes256Gcm::new(&key);
+    let cipher = Aes256Gcm::new(key);
 
     // Attempt decryption and capture the error
     match cipher.decrypt(nonce.into(), ciphertext) {
         Ok(decrypted_data) => {
-            let decrypted_password = String::from_utf8_lossy(&decrypted_data).to_string();
-            decrypted_password
+            String::from_utf8_lossy(&decrypted_data).to_string()
         }
         Err(e) => {
             eprintln!("Decryption failed with error: {:?}", e);
             panic!("Failed to decrypt the password");
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPOSED: &str = "caf\u{e9}-master-password";
+    const DECOMPOSED: &str = "cafe\u{301}-master-password";
+
+    #[test]
+    fn composed_and_decomposed_normalize_to_the_same_string() {
+        assert_ne!(COMPOSED, DECOMPOSED);
+        assert_eq!(normalize_master_password("\u{e9}"), normalize_master_password("e\u{301}"));
+        assert_eq!(normalize_master_password(COMPOSED), normalize_master_password(DECOMPOSED));
+    }
+
+    #[test]
+    fn both_forms_verify_against_the_same_hash() {
+        let hash = hash_master_password(&normalize_master_password(COMPOSED)).unwrap();
+
+        assert!(verify_master_password(&hash, &normalize_master_password(COMPOSED)));
+        assert!(verify_master_password(&hash, &normalize_master_password(DECOMPOSED)));
+    }
+
+    #[test]
+    fn both_forms_give_the_same_fingerprint() {
+        let hash = hash_master_password(&normalize_master_password(COMPOSED)).unwrap();
+
+        let composed = key_fingerprint(&normalize_master_password(COMPOSED), &hash).unwrap();
+        let decomposed = key_fingerprint(&normalize_master_password(DECOMPOSED), &hash).unwrap();
+        assert_eq!(composed, decomposed);
+    }
+
+    #[test]
+    fn candidates_try_the_normalized_form_first() {
+        assert_eq!(master_password_candidates(DECOMPOSED), vec![COMPOSED.to_string(), DECOMPOSED.to_string()]);
+        assert_eq!(master_password_candidates(COMPOSED), vec![COMPOSED.to_string()]);
+    }
+}
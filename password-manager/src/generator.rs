@@ -0,0 +1,225 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::compile_config::GENERATED_MIN_LENGTH;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>?/~";
+/// Characters easily mistaken for one another when read or typed, left out with `exclude_ambiguous`
+pub const AMBIGUOUS: &str = "0Oo1lI|";
+
+/// Which characters a generated password is built from
+/// 
+/// Also stored per account as its generation policy, for sites with password rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordOptions {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    /// Characters never used, ie. symbols a site doesn't accept
+    pub excluded: String,
+    /// Leaves out [`AMBIGUOUS`] characters, for passwords that are read aloud or typed from paper
+    pub exclude_ambiguous: bool,
+    /// Allows lengths below [`GENERATED_MIN_LENGTH`], for sites that cap the length
+    pub allow_short: bool,
+}
+
+impl Default for PasswordOptions {
+    fn default() -> Self {
+        PasswordOptions {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            excluded: String::new(),
+            exclude_ambiguous: false,
+            allow_short: false,
+        }
+    }
+}
+
+/// Generates a random password using the OS RNG
+///
+/// Every enabled character class appears at least once
+///
+/// # Returns
+///
+/// Returns an error if no class is enabled, every character of an enabled class is excluded,
+/// the length is below [`GENERATED_MIN_LENGTH`] without `allow_short`, or the length can't fit one of each class
+pub fn generate_password(options: &PasswordOptions) -> Result<String> {
+    let classes: Vec<Vec<u8>> = [
+        (options.lowercase, LOWERCASE),
+        (options.uppercase, UPPERCASE),
+        (options.digits, DIGITS),
+        (options.symbols, SYMBOLS),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, charset)| {
+        charset
+            .bytes()
+            .filter(|c| !options.excluded.contains(*c as char))
+            .filter(|c| !(options.exclude_ambiguous && AMBIGUOUS.contains(*c as char)))
+            .collect()
+    })
+    .collect();
+
+    if classes.is_empty() {
+        bail!("At least one character class must be enabled");
+    }
+    if classes.iter().any(|charset| charset.is_empty()) {
+        bail!("Every character of an enabled class is excluded, disable the class instead");
+    }
+    if options.length < GENERATED_MIN_LENGTH && !options.allow_short {
+        bail!("Length {} is below the minimum of {}, passwords that short are easy to guess", options.length, GENERATED_MIN_LENGTH);
+    }
+    if options.length < classes.len() {
+        bail!("Length {} is too short to include all {} enabled character classes", options.length, classes.len());
+    }
+
+    let mut rng = OsRng;
+    let all_chars: Vec<u8> = classes.concat();
+
+    // One from each class first, then fill the rest from everything and shuffle
+    let mut password: Vec<u8> = classes
+        .iter()
+        .map(|charset| charset[rng.gen_range(0..charset.len())])
+        .collect();
+    while password.len() < options.length {
+        password.push(all_chars[rng.gen_range(0..all_chars.len())]);
+    }
+    password.shuffle(&mut rng);
+
+    Ok(String::from_utf8(password).expect("Character sets are ASCII"))
+}
+
+/// Generates a passphrase of random words from the EFF large wordlist
+///
+/// Each word adds about 12.9 bits of entropy
+pub fn generate_passphrase(word_count: usize, separator: &str) -> Result<String> {
+    if word_count == 0 {
+        bail!("A passphrase needs at least one word");
+    }
+
+    let mut rng = OsRng;
+    let words: Vec<&str> = (0..word_count)
+        .map(|_| {
+            let (_, word) = eff_wordlist::large::LIST[rng.gen_range(0..eff_wordlist::large::LIST.len())];
+            word
+        })
+        .collect();
+
+    Ok(words.join(separator))
+}
+
+/// Character class counts of a password, see [`describe_password()`]
+#[derive(Debug, Clone, Copy)]
+pub struct Composition {
+    pub length: usize,
+    pub lowercase: usize,
+    pub uppercase: usize,
+    pub digits: usize,
+    /// Everything that isn't a letter or digit
+    pub symbols: usize,
+    pub entropy_bits: f64,
+}
+
+impl fmt::Display for Composition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} characters: {} lowercase, {} uppercase, {} digits, {} symbols, about {:.0} bits of entropy",
+            self.length, self.lowercase, self.uppercase, self.digits, self.symbols, self.entropy_bits
+        )
+    }
+}
+
+/// Counts the character classes in a password and estimates its entropy
+///
+/// The estimate assumes every character was picked at random from the classes the password uses,
+/// so it only means something for generated passwords, not ones chosen by a person
+pub fn describe_password(password: &str) -> Composition {
+    let mut composition = Composition {
+        length: 0,
+        lowercase: 0,
+        uppercase: 0,
+        digits: 0,
+        symbols: 0,
+        entropy_bits: 0.0,
+    };
+    for c in password.chars() {
+        composition.length += 1;
+        if c.is_ascii_lowercase() {
+            composition.lowercase += 1;
+        } else if c.is_ascii_uppercase() {
+            composition.uppercase += 1;
+        } else if c.is_ascii_digit() {
+            composition.digits += 1;
+        } else {
+            composition.symbols += 1;
+        }
+    }
+
+    let pool_size: usize = [
+        (composition.lowercase, LOWERCASE.len()),
+        (composition.uppercase, UPPERCASE.len()),
+        (composition.digits, DIGITS.len()),
+        (composition.symbols, SYMBOLS.len()),
+    ]
+    .iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(_, size)| size)
+    .sum();
+    if pool_size > 0 {
+        composition.entropy_bits = composition.length as f64 * (pool_size as f64).log2();
+    }
+
+    composition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options_with_length(length: usize) -> PasswordOptions {
+        PasswordOptions { length, ..PasswordOptions::default() }
+    }
+
+    #[test]
+    fn length_below_minimum_is_rejected() {
+        assert!(generate_password(&options_with_length(GENERATED_MIN_LENGTH - 1)).is_err());
+    }
+
+    #[test]
+    fn minimum_length_is_accepted() {
+        let password = generate_password(&options_with_length(GENERATED_MIN_LENGTH)).unwrap();
+        assert_eq!(password.len(), GENERATED_MIN_LENGTH);
+    }
+
+    #[test]
+    fn short_length_is_accepted_with_allow_short() {
+        let options = PasswordOptions { allow_short: true, ..options_with_length(GENERATED_MIN_LENGTH - 1) };
+        let password = generate_password(&options).unwrap();
+        assert_eq!(password.len(), GENERATED_MIN_LENGTH - 1);
+    }
+
+    #[test]
+    fn exclude_ambiguous_never_generates_look_alikes() {
+        let options = PasswordOptions { exclude_ambiguous: true, ..options_with_length(64) };
+
+        // Enough characters that a look-alike slipping through would show up
+        for _ in 0..1000 {
+            let password = generate_password(&options).unwrap();
+            assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)), "{} contains a look-alike character", password);
+        }
+    }
+}
@@ -1,23 +1,232 @@
+mod audit;
+mod cli;
 mod database;
 mod encryption;
+mod generator;
 mod user_interface;
 mod compile_config;
+mod diagnostics;
+mod password_policy;
+mod selftest;
+mod share;
+mod terminal;
+mod os_keyring;
+mod messages;
+mod pass_store;
+mod vault_diff;
 
+use clap::Parser;
+use compile_config::DB_BUSY_TIMEOUT_MS;
+use cli::{run_audit, run_diff, run_gen, run_get, run_import_pass, run_import_shared, run_list, run_repair, run_scan_plaintext, run_share, run_username, Cli, Command};
+#[cfg(not(feature = "sqlcipher"))]
 use database::initialize_db;
-use user_interface::start_ui_loop;
-use std::process;
+use diagnostics::debug_log;
+use database::{acquire_vault_lock, initialize_memory_db, load_vault_into_memory, prepare_db_file, release_vault_lock, restrict_db_file_permissions, save_memory_vault};
+use encryption::read_keyfile;
+use selftest::run_selftest;
+use terminal::TerminalState;
+use user_interface::{confirm, disable_login_retry, enable_keyring, set_keyfile, start_ui_loop};
+#[cfg(feature = "sqlcipher")]
+use user_interface::prompt_database_key;
+use sqlx::sqlite::SqlitePool;
+use std::{path::Path, process, time::Duration};
+#[cfg(feature = "sqlcipher")]
+use zeroize::Zeroize;
+
+/// Opens the vault file, asking for the SQLCipher key first in SQLCipher builds
+async fn open_vault_file(db_path: &Path) -> anyhow::Result<SqlitePool> {
+    debug_log!("Opening database {}", db_path.display());
+    #[cfg(feature = "sqlcipher")]
+    {
+        let mut keys = prompt_database_key()?;
+        let mut result = Err(anyhow::anyhow!("No database key entered"));
+        for key in &keys {
+            result = database::initialize_encrypted_db(db_path, key).await;
+            if result.is_ok() {
+                break;
+            }
+        }
+        keys.zeroize();
+        result
+    }
+    #[cfg(not(feature = "sqlcipher"))]
+    initialize_db(db_path).await
+}
+
+/// Opens an in-memory vault for `--ephemeral`, starting from a copy of `load_from` if given
+async fn open_ephemeral_vault(load_from: Option<&Path>) -> anyhow::Result<SqlitePool> {
+    // SQLCipher files can't be attached without their key
+    if cfg!(feature = "sqlcipher") && load_from.is_some() {
+        anyhow::bail!("--load-from is not available in SQLCipher builds");
+    }
+
+    debug_log!("Opening in-memory database");
+    let pool = initialize_memory_db().await?;
+    if let Some(source) = load_from {
+        load_vault_into_memory(&pool, source).await?;
+    }
+    Ok(pool)
+}
 
 #[tokio::main]
 async fn main() {
-    // Initialize the database connection
-    let pool = match initialize_db().await {
+    let cli = Cli::parse();
+
+    if let Err(e) = messages::select_language(cli.lang.as_deref()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    // Commands that never open the vault
+    match &cli.command {
+        Some(Command::Selftest) => {
+            if !run_selftest().await {
+                process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Gen(args)) => {
+            match run_gen(args) {
+                Ok(generated) => println!("{}", generated),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    match cli.keyfile.as_deref().map(read_keyfile).transpose() {
+        Ok(Some(keyfile)) => {
+            debug_log!("Key file loaded");
+            set_keyfile(keyfile);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{:#}", e);
+            process::exit(1);
+        }
+    }
+
+    if cli.no_retry {
+        disable_login_retry();
+    }
+
+    // An in-memory vault has no file to prepare, lock or keep a keyring entry for
+    let db_path = cli.db_path.as_path();
+    let pool_result = if cli.ephemeral {
+        open_ephemeral_vault(cli.load_from.as_deref()).await
+    } else {
+        // Creating directories from the interactive menu is confirmed first, subcommands just create them
+        if let Some(parent) = db_path.parent().filter(|parent| !parent.as_os_str().is_empty() && !parent.exists()) {
+            if cli.command.is_none() {
+                let prompt = format!("Directory {} does not exist. Create it?", parent.display());
+                if !matches!(confirm(&prompt), Ok(true)) {
+                    eprintln!("No database directory, exiting.");
+                    process::exit(1);
+                }
+            }
+        }
+        if let Err(e) = prepare_db_file(db_path) {
+            eprintln!("{:#}", e);
+            process::exit(1);
+        }
+
+        // Only the interactive menu is locked, subcommands are short and SQLite serializes their writes
+        if cli.command.is_none() {
+            if let Err(e) = acquire_vault_lock(db_path) {
+                eprintln!("{:#}", e);
+                process::exit(1);
+            }
+        }
+
+        // Keyring entries are tied to the vault file they were stored for
+        if cli.use_keyring {
+            let vault = db_path.canonicalize().unwrap_or_else(|_| db_path.to_path_buf());
+            debug_log!("OS keyring enabled for {}", vault.display());
+            enable_keyring(vault.display().to_string());
+        }
+
+        open_vault_file(db_path).await
+    };
+
+    let pool = match pool_result {
         Ok(valid_pool) => valid_pool,
         Err(e) => {
             eprintln!("Failed to connect to database: {}", e);
+            release_vault_lock();
             process::exit(1);
         }
     };
 
-    // Start the user interface loop
-    start_ui_loop(&pool).await;
+    // Close the pool on Ctrl-C as well, so the WAL is checkpointed and the -wal/-shm files are removed
+    let terminal = TerminalState::save();
+    let signal_pool = pool.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        // The signal may arrive in the middle of a hidden password prompt
+        if let Some(terminal) = &terminal {
+            terminal.restore();
+        }
+        eprintln!();
+        eprintln!("Interrupted, closing the database...");
+        // Don't hang on a connection that is never returned
+        let _ = tokio::time::timeout(Duration::from_millis(DB_BUSY_TIMEOUT_MS), signal_pool.close()).await;
+        release_vault_lock();
+        process::exit(130);
+    });
+
+    // Checked after connecting so the -wal/-shm files exist too
+    if !cli.ephemeral {
+        match restrict_db_file_permissions(db_path) {
+            Ok(loosened) => {
+                for file in loosened {
+                    eprintln!("Warning: {} was readable by other users, restricted it to owner only (0600).", file.display());
+                }
+            }
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    let result = match &cli.command {
+        // Start the user interface loop
+        None => {
+            start_ui_loop(&pool).await;
+            Ok(())
+        }
+        Some(Command::List(args)) => run_list(&pool, args).await,
+        Some(Command::Get(args)) => run_get(&pool, args).await,
+        Some(Command::Username(args)) => run_username(&pool, args).await,
+        Some(Command::Repair) => run_repair(&pool).await,
+        Some(Command::ScanPlaintext) => run_scan_plaintext(&pool).await,
+        Some(Command::Share(args)) => run_share(&pool, args).await,
+        Some(Command::ImportShared(args)) => run_import_shared(&pool, args).await,
+        Some(Command::ImportPass(args)) => run_import_pass(&pool, args).await,
+        Some(Command::Audit(args)) => run_audit(&pool, args).await,
+        Some(Command::Diff(args)) => run_diff(&pool, args).await,
+        Some(Command::Selftest | Command::Gen(_)) => unreachable!("Handled before opening the vault"),
+    };
+
+    // Saved even when the command failed, anything changed before the error would be lost otherwise
+    let save_result = match &cli.save_to {
+        Some(dest) => save_memory_vault(&pool, dest).await.map(|_| eprintln!("Vault saved to {}.", dest.display())),
+        None => Ok(()),
+    };
+
+    pool.close().await;
+    release_vault_lock();
+
+    if let Err(e) = save_result {
+        eprintln!("{:#}", e);
+        process::exit(1);
+    }
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
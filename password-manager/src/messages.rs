@@ -0,0 +1,109 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+/// Message tables bundled into the binary, English first since every other language falls back to it
+const BUNDLED: [(&str, &str); 2] = [
+    ("en", include_str!("../messages/en.toml")),
+    ("de", include_str!("../messages/de.toml")),
+];
+
+static TABLES: OnceLock<HashMap<&'static str, HashMap<String, String>>> = OnceLock::new();
+static LANGUAGE: OnceLock<&'static str> = OnceLock::new();
+
+fn tables() -> &'static HashMap<&'static str, HashMap<String, String>> {
+    TABLES.get_or_init(|| {
+        BUNDLED
+            .iter()
+            .map(|(language, source)| {
+                let table = toml::from_str(source)
+                    .unwrap_or_else(|e| panic!("Bundled messages/{}.toml is not a valid string table: {}", language, e));
+                (*language, table)
+            })
+            .collect()
+    })
+}
+
+/// Language codes there is a bundled translation for
+pub fn available_languages() -> impl Iterator<Item = &'static str> {
+    BUNDLED.iter().map(|(language, _)| *language)
+}
+
+/// Picks the language for [`t()`], from `--lang` if given, otherwise from `LC_ALL`, `LC_MESSAGES` or `LANG`
+/// 
+/// Locale names are reduced to the language, so `de_DE.UTF-8` selects `de`.
+/// Returns an error for a `--lang` without a bundled translation, an unknown locale from the environment
+/// just stays English. Only the first call has an effect
+pub fn select_language(requested: Option<&str>) -> anyhow::Result<()> {
+    let language = match requested {
+        Some(requested) => match bundled_language(requested) {
+            Some(language) => language,
+            None => anyhow::bail!(
+                "No translation for language '{}', available: {}",
+                requested,
+                available_languages().collect::<Vec<_>>().join(", ")
+            ),
+        },
+        // The first non-empty variable wins, as in POSIX locale lookup
+        None => ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| bundled_language(&locale))
+            .unwrap_or("en"),
+    };
+
+    let _ = LANGUAGE.set(language);
+    Ok(())
+}
+
+/// `de`, `de_DE.UTF-8` and `de-AT` all map to `de`, `C` and `POSIX` to nothing
+fn bundled_language(locale: &str) -> Option<&'static str> {
+    let language = locale.split(['_', '-', '.', '@']).next().unwrap_or_default().to_lowercase();
+    available_languages().find(|bundled| *bundled == language)
+}
+
+/// Looks up a user-facing string by message id, ie. `t("menu.exit")`
+/// 
+/// Falls back to English when the selected language has no text for the id. An id unknown
+/// in English too is returned as it is, so a typo shows up in the output instead of an empty line
+pub fn t(id: &str) -> &str {
+    let language = LANGUAGE.get().copied().unwrap_or("en");
+    let text = [language, "en"]
+        .iter()
+        .find_map(|language| tables().get(language).and_then(|table| table.get(id)));
+
+    match text {
+        Some(text) => text,
+        None => {
+            debug_assert!(false, "Unknown message id {}", id);
+            id
+        }
+    }
+}
+
+/// Like [`t()`], filling in `{name}` placeholders
+/// 
+/// Values are inserted after the lookup, so user data such as account names is never looked up itself
+pub fn t_with(id: &str, args: &[(&str, &dyn std::fmt::Display)]) -> String {
+    let mut text = String::new();
+    let mut rest = t(id);
+    // One pass, so a value that looks like a placeholder is left alone
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('}')
+            .and_then(|end| args.iter().find(|(name, _)| *name == &after[..end]).map(|(_, value)| (end, value)));
+        match value {
+            Some((end, value)) => {
+                text.push_str(&value.to_string());
+                rest = &after[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
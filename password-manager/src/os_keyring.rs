@@ -0,0 +1,35 @@
+// Optional convenience for trusted machines, enabled with --use-keyring.
+// Anyone who can use the OS account can read the keyring, so the vault is then only
+// as safe as the OS login. The key file, if required, is still needed.
+
+use keyring::Entry;
+
+const KEYRING_SERVICE: &str = "password-manager";
+
+/// Entries are per vault file and master username
+fn keyring_entry(vault: &str, username: &str) -> keyring::Result<Entry> {
+    Entry::new(KEYRING_SERVICE, &format!("{}@{}", username, vault))
+}
+
+/// Stores the plain master password, never combined with the key file
+pub fn store_master_in_keyring(vault: &str, username: &str, password: &str) -> anyhow::Result<()> {
+    keyring_entry(vault, username)?.set_password(password)?;
+    Ok(())
+}
+
+/// Returns the stored master password, or None if there is none
+pub fn fetch_master_from_keyring(vault: &str, username: &str) -> anyhow::Result<Option<String>> {
+    match keyring_entry(vault, username)?.get_password() {
+        Ok(password) => Ok(Some(password)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Removes the stored master password, succeeds if there was none
+pub fn delete_master_from_keyring(vault: &str, username: &str) -> anyhow::Result<()> {
+    match keyring_entry(vault, username)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,250 @@
+use std::{fs, path::{Path, PathBuf}, process::Command};
+
+use anyhow::Context;
+use sqlx::sqlite::SqlitePool;
+use zeroize::Zeroize;
+
+use crate::{database::{add_account, get_account_by_name, Account}, encryption::encrypt_password};
+
+/// Metadata keys `pass` users commonly put the username under
+const USERNAME_KEYS: [&str; 4] = ["login", "username", "user", "email"];
+const URL_KEYS: [&str; 2] = ["url", "website"];
+
+/// Outcome of [`import_pass_store()`], entries are account names
+#[derive(Debug, Default)]
+pub struct PassImportSummary {
+    pub imported: Vec<String>,
+    /// Entries left out and why, ie. the name is already taken or gpg couldn't decrypt it
+    pub skipped: Vec<(String, String)>,
+}
+
+/// One decrypted entry in the multiline pass format
+struct PassEntry {
+    password: String,
+    username: Option<String>,
+    url: Option<String>,
+    /// Every other line after the password
+    notes: Option<String>,
+}
+
+impl Drop for PassEntry {
+    fn drop(&mut self) {
+        self.password.zeroize();
+
+        if let Some(ref mut notes) = self.notes {
+            notes.zeroize();
+        }
+    }
+}
+
+/// An entry about to be imported, the fields can still be edited while reviewing
+pub struct ImportCandidate {
+    pub name: String,
+    pub username: String,
+    pub password: String,
+    pub url: Option<String>,
+    /// Kept as a sensitive note
+    pub notes: Option<String>,
+}
+
+impl Drop for ImportCandidate {
+    fn drop(&mut self) {
+        self.username.zeroize();
+        self.password.zeroize();
+
+        if let Some(ref mut notes) = self.notes {
+            notes.zeroize();
+        }
+    }
+}
+
+/// Default store location, the same one `pass` uses
+pub fn default_store_path() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("PASSWORD_STORE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".password-store"))
+}
+
+/// What to do with a candidate shown by the review callback of [`import_pass_store()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewChoice {
+    Include,
+    Skip,
+    /// Include this one and every remaining candidate without asking
+    IncludeAll,
+}
+
+/// Imports every entry of a `pass` store, decrypting them with the local gpg agent
+///
+/// The path inside the store becomes the account name (`work/github.gpg` is `work/github`).
+/// The first line is the password, `login:`/`url:` style lines fill in the username and URL
+/// and the remaining lines are kept as a sensitive note. Without a login line the file name is
+/// used as the username, as pass browser extensions do.
+///
+/// `review` is called with each candidate before it is inserted and may edit it, pass
+/// `|_| Ok(ReviewChoice::IncludeAll)` to import everything.
+/// Entries whose name is already taken are skipped, nothing is overwritten
+pub async fn import_pass_store(
+    pool: &SqlitePool,
+    store_path: &Path,
+    master_password: &String,
+    mut review: impl FnMut(&mut ImportCandidate) -> std::io::Result<ReviewChoice>,
+) -> anyhow::Result<PassImportSummary> {
+    let mut summary = PassImportSummary::default();
+    let candidates = read_pass_store(store_path, &mut summary)?;
+
+    let mut include_all = false;
+    for mut candidate in candidates {
+        if !include_all {
+            match review(&mut candidate)? {
+                ReviewChoice::Include => {}
+                ReviewChoice::IncludeAll => include_all = true,
+                ReviewChoice::Skip => {
+                    summary.skipped.push((candidate.name.clone(), "skipped while reviewing".to_string()));
+                    continue;
+                }
+            }
+        }
+
+        if get_account_by_name(pool, &candidate.name).await.is_ok() {
+            summary.skipped.push((candidate.name.clone(), "an account with this name already exists".to_string()));
+            continue;
+        }
+
+        let notes = candidate.notes.as_ref().map(|notes| encrypt_password(master_password, notes));
+        let mut account = Account::new(
+            candidate.name.clone(),
+            candidate.username.clone(),
+            encrypt_password(master_password, &candidate.password),
+            candidate.url.clone(),
+            notes,
+        );
+        account.encrypted_note = account.description.is_some();
+
+        add_account(pool, &account).await?;
+        summary.imported.push(candidate.name.clone());
+    }
+
+    Ok(summary)
+}
+
+/// Decrypts every entry of the store into candidates, entries that can't be read are added to `summary` as skipped
+fn read_pass_store(store_path: &Path, summary: &mut PassImportSummary) -> anyhow::Result<Vec<ImportCandidate>> {
+    let mut files = Vec::new();
+    collect_entries(store_path, &mut files)
+        .with_context(|| format!("Failed to read pass store {}", store_path.display()))?;
+    files.sort();
+
+    let mut candidates = Vec::new();
+    for file in files {
+        let name = entry_name(store_path, &file);
+        let entry = match decrypt_entry(&file) {
+            Ok(entry) => entry,
+            Err(err) => {
+                summary.skipped.push((name, err.to_string()));
+                continue;
+            }
+        };
+
+        let username = entry.username.clone().unwrap_or_else(|| {
+            file.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+        });
+        candidates.push(ImportCandidate {
+            name,
+            username,
+            password: entry.password.clone(),
+            url: entry.url.clone(),
+            notes: entry.notes.clone(),
+        });
+    }
+
+    Ok(candidates)
+}
+
+/// Finds the `.gpg` files in the store, skipping hidden directories such as `.git`
+fn collect_entries(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+
+        if path.is_dir() {
+            collect_entries(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "gpg") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Store-relative path without the extension, always `/` separated
+fn entry_name(store_path: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(store_path).unwrap_or(file).with_extension("");
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn decrypt_entry(file: &Path) -> anyhow::Result<PassEntry> {
+    let output = Command::new("gpg")
+        .args(["--quiet", "--batch", "--decrypt"])
+        .arg(file)
+        .output()
+        .context("Failed to run gpg, is it installed?")?;
+
+    let mut stdout = output.stdout;
+    if !output.status.success() {
+        stdout.zeroize();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("gpg could not decrypt it ({})", stderr.lines().last().unwrap_or("no error message").trim());
+    }
+
+    let contents = String::from_utf8(stdout);
+    let mut contents = match contents {
+        Ok(contents) => contents,
+        Err(err) => {
+            err.into_bytes().zeroize();
+            anyhow::bail!("entry is not valid UTF-8");
+        }
+    };
+    let entry = parse_entry(&contents);
+    contents.zeroize();
+
+    entry
+}
+
+/// Splits the pass format: password on the first line, metadata after
+fn parse_entry(contents: &str) -> anyhow::Result<PassEntry> {
+    let mut lines = contents.lines();
+    let password = lines.next().unwrap_or_default().to_string();
+    if password.is_empty() {
+        anyhow::bail!("entry has no password on its first line");
+    }
+
+    let mut entry = PassEntry { password, username: None, url: None, notes: None };
+    let mut notes = Vec::new();
+    for line in lines {
+        let field = line.split_once(':').map(|(key, value)| (key.trim().to_lowercase(), value.trim()));
+        match field {
+            Some((key, value)) if entry.username.is_none() && !value.is_empty() && USERNAME_KEYS.contains(&key.as_str()) => {
+                entry.username = Some(value.to_string());
+            }
+            // `url: https://...` splits on the first colon, so the scheme stays in the value
+            Some((key, value)) if entry.url.is_none() && !value.is_empty() && URL_KEYS.contains(&key.as_str()) => {
+                entry.url = Some(value.to_string());
+            }
+            _ => notes.push(line),
+        }
+    }
+
+    let mut notes = notes.join("\n");
+    if !notes.trim().is_empty() {
+        entry.notes = Some(notes.trim().to_string());
+    }
+    notes.zeroize();
+    Ok(entry)
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+use crate::compile_config::{MASTER_MIN_CHARACTER_CLASSES, MASTER_MIN_LENGTH};
+
+/// Requirements a master password must meet before it is accepted
+#[derive(Debug, Clone, Copy)]
+pub struct MasterPasswordPolicy {
+    pub min_length: usize,
+    /// Number of distinct character classes (lowercase, uppercase, digits, symbols) required
+    pub min_character_classes: usize,
+}
+
+impl Default for MasterPasswordPolicy {
+    fn default() -> Self {
+        MasterPasswordPolicy {
+            min_length: MASTER_MIN_LENGTH,
+            min_character_classes: MASTER_MIN_CHARACTER_CLASSES,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PolicyError {
+    TooShort { min_length: usize, length: usize },
+    TooFewCharacterClasses { required: usize, found: usize },
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::TooShort { min_length, length } => write!(
+                f,
+                "Master password must be at least {} characters long (got {})",
+                min_length, length
+            ),
+            PolicyError::TooFewCharacterClasses { required, found } => write!(
+                f,
+                "Master password must mix at least {} of: lowercase letters, uppercase letters, digits, symbols (got {})",
+                required, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Counts how many character classes (lowercase, uppercase, digits, symbols) appear in the password
+fn count_character_classes(password: &str) -> usize {
+    let has_lowercase = password.chars().any(|c| c.is_lowercase());
+    let has_uppercase = password.chars().any(|c| c.is_uppercase());
+    let has_digit = password.chars().any(|c| c.is_numeric());
+    let has_symbol = password.chars().any(|c| !c.is_alphanumeric());
+
+    [has_lowercase, has_uppercase, has_digit, has_symbol]
+        .iter()
+        .filter(|&&present| present)
+        .count()
+}
+
+/// Checks a candidate master password against the policy
+///
+/// Length is counted in characters, not bytes
+pub fn validate_master_strength(password: &str, policy: &MasterPasswordPolicy) -> Result<(), PolicyError> {
+    let length = password.chars().count();
+    if length < policy.min_length {
+        return Err(PolicyError::TooShort { min_length: policy.min_length, length });
+    }
+
+    let found = count_character_classes(password);
+    if found < policy.min_character_classes {
+        return Err(PolicyError::TooFewCharacterClasses { required: policy.min_character_classes, found });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::panic::{self, UnwindSafe};
+
+use anyhow::{anyhow, bail};
+use rand_core::{OsRng, RngCore};
+
+use crate::{database::{add_account, add_master, get_account_by_name, initialize_memory_db, verify_master, Account, Master}, encryption::{decrypt_password, encrypt_password, hash_master_password, verify_master_password}};
+
+const SAMPLE_MASTER_PASSWORD: &str = "selftest-master-password";
+const SAMPLE_ACCOUNT_NAME: &str = "selftest";
+const SAMPLE_PASSWORD: &str = "selftest-account-password";
+
+/// Runs each subsystem check and prints OK/FAIL for it
+///
+/// Only uses an in-memory database, the real vault is never opened
+///
+/// # Returns
+///
+/// Returns true if every check passed
+pub async fn run_selftest() -> bool {
+    println!("Running self-test...");
+
+    let results = [
+        report("Random number generator", check_rng()),
+        report("Master password hashing", check_master_hashing()),
+        report("Password encryption", check_encryption()),
+        report("Database round trip", check_database().await),
+    ];
+
+    let passed = results.iter().all(|&ok| ok);
+    if passed {
+        println!("All checks passed.");
+    } else {
+        println!("One or more checks failed.");
+    }
+    passed
+}
+
+fn report(subsystem: &str, result: anyhow::Result<()>) -> bool {
+    match result {
+        Ok(()) => {
+            println!("{:<26} OK", subsystem);
+            true
+        }
+        Err(err) => {
+            println!("{:<26} FAIL ({})", subsystem, err);
+            false
+        }
+    }
+}
+
+/// The encryption functions panic on failure, turn that into an error instead
+fn catch_panic<T>(f: impl FnOnce() -> T + UnwindSafe) -> anyhow::Result<T> {
+    panic::catch_unwind(f).map_err(|_| anyhow!("operation panicked"))
+}
+
+fn check_rng() -> anyhow::Result<()> {
+    let mut bytes = [0u8; 32];
+    OsRng.try_fill_bytes(&mut bytes).map_err(|e| anyhow!("OS RNG unavailable: {}", e))?;
+
+    if bytes.iter().all(|&b| b == 0) {
+        bail!("OS RNG returned only zeroes");
+    }
+    Ok(())
+}
+
+fn check_master_hashing() -> anyhow::Result<()> {
+    let master_password = SAMPLE_MASTER_PASSWORD.to_string();
+    let hash = hash_master_password(&master_password)?;
+
+    if !verify_master_password(&hash, &master_password) {
+        bail!("correct password was rejected");
+    }
+    if verify_master_password(&hash, &"wrong-password".to_string()) {
+        bail!("wrong password was accepted");
+    }
+    Ok(())
+}
+
+fn check_encryption() -> anyhow::Result<()> {
+    let master_password = SAMPLE_MASTER_PASSWORD.to_string();
+    let password = SAMPLE_PASSWORD.to_string();
+
+    let encrypted = catch_panic(|| encrypt_password(&master_password, &password))?;
+    if encrypted.contains(SAMPLE_PASSWORD) {
+        bail!("ciphertext contains the plaintext");
+    }
+
+    let decrypted = catch_panic(|| decrypt_password(&master_password, &encrypted))?;
+    if decrypted != SAMPLE_PASSWORD {
+        bail!("decrypted password does not match the original");
+    }
+    Ok(())
+}
+
+async fn check_database() -> anyhow::Result<()> {
+    let pool = initialize_memory_db().await?;
+    let master_password = SAMPLE_MASTER_PASSWORD.to_string();
+
+    let master = Master::new("selftest".to_string(), hash_master_password(&master_password)?);
+    add_master(&pool, &master).await?;
+    if !verify_master(&pool, &master.username, &master_password).await? {
+        bail!("stored master could not be verified");
+    }
+
+    let encrypted = catch_panic(|| encrypt_password(&master_password, &SAMPLE_PASSWORD.to_string()))?;
+    let account = Account::new(SAMPLE_ACCOUNT_NAME.to_string(), "selftest-user".to_string(), encrypted, None, None);
+    add_account(&pool, &account).await?;
+
+    let stored = get_account_by_name(&pool, &SAMPLE_ACCOUNT_NAME.to_string()).await?;
+    let decrypted = catch_panic(|| decrypt_password(&master_password, &stored.password))?;
+    if decrypted != SAMPLE_PASSWORD {
+        bail!("stored password did not decrypt to the original");
+    }
+
+    pool.close().await;
+    Ok(())
+}
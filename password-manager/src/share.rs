@@ -0,0 +1,120 @@
+use std::{fs::{self, OpenOptions}, io::Write, path::Path, str::FromStr};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use zeroize::Zeroize;
+
+use crate::{database::get_account_by_id, encryption::try_decrypt_password};
+
+/// A single account in plaintext, only ever written to disk encrypted to a recipient
+#[derive(Serialize, Deserialize)]
+pub struct SharedAccount {
+    pub name: String,
+    pub username: String,
+    pub password: String,
+    pub url: Option<String>,
+    pub description: Option<String>,
+    /// The description was a sensitive note and should be encrypted again on import
+    #[serde(default)]
+    pub encrypted_note: bool,
+    #[serde(default)]
+    pub login_steps: Option<String>,
+    /// The login steps were encrypted and should be encrypted again on import
+    #[serde(default)]
+    pub encrypted_login_steps: bool,
+    #[serde(default)]
+    pub change_password_url: Option<String>,
+}
+
+impl Drop for SharedAccount {
+    fn drop(&mut self) {
+        self.username.zeroize();
+        self.password.zeroize();
+
+        if let Some(ref mut description) = self.description {
+            description.zeroize();
+        }
+        if let Some(ref mut login_steps) = self.login_steps {
+            login_steps.zeroize();
+        }
+    }
+}
+
+/// Exports one account encrypted to another user's age public key (age1...)
+/// 
+/// The file is ASCII armored and can only be decrypted with the recipient's private key,
+/// an existing file is never overwritten
+pub async fn export_shared(pool: &SqlitePool, account_id: i64, master_password: &String, recipient_pubkey: &str, path: &Path) -> anyhow::Result<()> {
+    let recipient = age::x25519::Recipient::from_str(recipient_pubkey.trim())
+        .map_err(|e| anyhow::anyhow!("Invalid age public key: {}", e))?;
+
+    let account = get_account_by_id(pool, account_id).await?;
+    let description = match &account.description {
+        Some(description) if account.encrypted_note => Some(try_decrypt_password(master_password, description)?),
+        description => description.clone(),
+    };
+    let login_steps = match &account.login_steps {
+        Some(steps) if account.encrypted_login_steps => Some(try_decrypt_password(master_password, steps)?),
+        steps => steps.clone(),
+    };
+    let shared = SharedAccount {
+        name: account.name.clone(),
+        username: account.username.clone(),
+        password: try_decrypt_password(master_password, &account.password)?,
+        url: account.url.clone(),
+        description,
+        encrypted_note: account.encrypted_note,
+        login_steps,
+        encrypted_login_steps: account.encrypted_login_steps,
+        change_password_url: account.change_password_url.clone(),
+    };
+
+    let mut plaintext = serde_json::to_vec(&shared)?;
+    let armored = age::encrypt_and_armor(&recipient, &plaintext);
+    plaintext.zeroize();
+    let armored = armored?;
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(armored.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Decrypts an account exported with [`export_shared()`]
+/// 
+/// `private_key` is the recipient's age identity (AGE-SECRET-KEY-1...)
+pub fn import_shared(path: &Path, private_key: &str) -> anyhow::Result<SharedAccount> {
+    let identity = age::x25519::Identity::from_str(private_key.trim())
+        .map_err(|e| anyhow::anyhow!("Invalid age private key: {}", e))?;
+
+    let ciphertext = fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut plaintext = age::decrypt(&identity, &ciphertext)
+        .map_err(|e| anyhow::anyhow!("Failed to decrypt {}, was it shared with this key? ({})", path.display(), e))?;
+
+    let shared = serde_json::from_slice(&plaintext).context("Shared file does not contain an account");
+    plaintext.zeroize();
+
+    shared
+}
+
+/// Reads the private key from an age identity file, as written by age-keygen
+pub fn read_identity_file(path: &Path) -> anyhow::Result<String> {
+    let mut contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read identity file {}", path.display()))?;
+
+    // Comment lines hold the creation date and public key
+    let key = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+        .map(str::to_string);
+    contents.zeroize();
+
+    key.with_context(|| format!("No age private key found in {}", path.display()))
+}
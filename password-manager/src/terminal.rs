@@ -0,0 +1,130 @@
+// rpassword switches the terminal to raw mode while reading and only switches it back once
+// the read returns. If a read is abandoned (prompt timeout), the terminal has to be restored by hand.
+
+#[cfg(unix)]
+use std::{fs::File, os::fd::AsRawFd};
+use std::{io::{self, Write}, thread, time::Duration};
+
+use crossterm::{cursor::{MoveToColumn, MoveToPreviousLine}, queue, style::Print, terminal::{Clear, ClearType}};
+use zeroize::Zeroize;
+
+/// Terminal mode captured before a password prompt
+#[cfg(unix)]
+pub struct TerminalState {
+    tty: File,
+    termios: libc::termios,
+}
+
+#[cfg(unix)]
+impl TerminalState {
+    /// Captures the current mode of the controlling terminal, if there is one
+    pub fn save() -> Option<Self> {
+        let tty = File::open("/dev/tty").ok()?;
+
+        // SAFETY: termios is plain data, tcgetattr fills it in and only reads the fd
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut termios) } != 0 {
+            return None;
+        }
+
+        Some(TerminalState { tty, termios })
+    }
+
+    /// Puts the terminal back into the captured mode (echo and line buffering)
+    pub fn restore(&self) {
+        // SAFETY: termios was filled in by tcgetattr on the same terminal
+        unsafe {
+            libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.termios);
+        }
+    }
+}
+
+/// Discards input that is already waiting on the terminal, returns whether there was any
+/// 
+/// Called right after a line is read, leftover input then means several lines were pasted at once.
+/// In canonical mode only complete lines are counted, so the queue is checked with it switched off
+#[cfg(unix)]
+pub fn discard_pending_input() -> bool {
+    let Some(state) = TerminalState::save() else {
+        return false;
+    };
+    let fd = state.tty.as_raw_fd();
+    let mut uncooked = state.termios;
+    uncooked.c_lflag &= !libc::ICANON;
+
+    // SAFETY: uncooked is a copy of the captured mode, FIONREAD writes the number of queued bytes into queued
+    let pending = unsafe {
+        let mut queued: libc::c_int = 0;
+        let pending = libc::tcsetattr(fd, libc::TCSANOW, &uncooked) == 0
+            && libc::ioctl(fd, libc::FIONREAD, &mut queued) == 0
+            && queued > 0;
+        if pending {
+            libc::tcflush(fd, libc::TCIFLUSH);
+        }
+        pending
+    };
+    state.restore();
+
+    pending
+}
+
+/// Not supported outside Unix, the console mode is left as is
+#[cfg(not(unix))]
+pub struct TerminalState;
+
+#[cfg(not(unix))]
+impl TerminalState {
+    pub fn save() -> Option<Self> {
+        None
+    }
+
+    pub fn restore(&self) {}
+}
+
+#[cfg(not(unix))]
+pub fn discard_pending_input() -> bool {
+    false
+}
+
+/// Columns assumed when stdout isn't a terminal, ie. when output is piped
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+
+/// Width of the terminal stdout is attached to, or [`DEFAULT_TERMINAL_WIDTH`]
+pub fn terminal_width() -> usize {
+    match terminal_size::terminal_size() {
+        Some((terminal_size::Width(width), _)) if width > 0 => width as usize,
+        _ => DEFAULT_TERMINAL_WIDTH,
+    }
+}
+
+/// Shows `label: secret` on the current line of the terminal with a countdown, then clears it again
+/// 
+/// Only that line is rewritten, everything printed before it stays on screen. Blocks for `secs` seconds
+pub fn reveal_for(label: &str, secret: &str, secs: u64) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    // Rows the previous countdown took up, a secret longer than the terminal wraps onto several
+    let mut rows = 1;
+
+    for remaining in (1..=secs).rev() {
+        let mut line = format!("{}: {} (hidden in {}s)", label, secret, remaining);
+        clear_rows(&mut stdout, rows)?;
+        rows = line.chars().count().div_ceil(terminal_width()).max(1);
+        let printed = queue!(stdout, Print(&line)).and_then(|_| stdout.flush());
+        line.zeroize();
+        printed?;
+
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    clear_rows(&mut stdout, rows)?;
+    queue!(stdout, Print(format!("{}: hidden\n", label)))?;
+    stdout.flush()
+}
+
+/// Clears the cursor's row and the `rows - 1` rows above it, leaving the cursor at the start of the top one
+fn clear_rows(stdout: &mut io::Stdout, rows: usize) -> io::Result<()> {
+    if rows > 1 {
+        queue!(stdout, MoveToPreviousLine((rows - 1) as u16))?;
+    }
+    queue!(stdout, MoveToColumn(0), Clear(ClearType::FromCursorDown))
+}
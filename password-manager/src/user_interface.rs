@@ -1,351 +1,1923 @@
-use std::{io::{self, Write}, process};
-use sqlx::{sqlite::{SqliteConnectOptions, SqlitePool}, Sqlite};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{collections::{BTreeMap, HashMap}, fs::{self, OpenOptions}, io::{self, IsTerminal, Write}, path::{Path, PathBuf}, process, sync::{atomic::{AtomicBool, Ordering}, mpsc, OnceLock}, thread, time::Duration};
+use dialoguer::FuzzySelect;
+use sqlx::sqlite::SqlitePool;
+use sqlx::types::chrono::{Local, NaiveDate};
 use zeroize::Zeroize;
 
-use crate::{compile_config::{DEBUG_FLAG, SINGLE_MASTER_FLAG}, database::{add_account, delete_account_by_id, delete_account_by_name, get_account_by_id, get_account_by_name, get_master_by_username, list_accounts, update_account, update_master, verify_master, Account, AccountSummary, Master}, encryption::{decrypt_password, encrypt_password, hash_master_password}};
+#[cfg(feature = "sqlcipher")]
+use crate::database::rekey_db;
+use crate::{audit::AccountHealth, messages::{t, t_with}, compile_config::{AUTO_BACKUP_DIR, AUTO_BACKUP_KEEP, GENERATED_MIN_LENGTH, LOGIN_PREVIEW_COUNT, MASTER_PROMPT_TIMEOUT_SECS, MAX_ATTACHMENT_SIZE, PASSWORD_REVEAL_SECS, PLAIN_PASSWORD_INPUT, PROMPT_OPTIONAL_FIELDS, REAUTH_DELETE_ACCOUNT, REAUTH_DELETE_ATTACHMENT, REPEAT_LAST_ACTION, SINGLE_MASTER_FLAG, TIMED_PASSWORD_REVEAL}, diagnostics::debug_log, database::{add_account, add_attachment, add_master, auto_backup, backup_vault, change_master_password, clone_account, count_accounts, db_file_path, delete_attachment_by_id, delete_account_by_id, get_account_by_id, get_account_by_name, get_attachment_by_id, find_accounts_using_password, get_first_master, get_expiry, get_generation_policy, get_master_by_id, get_master_by_username, is_default_master, is_excluded_from_audit, is_not_found, list_accounts, list_accounts_grouped, list_accounts_paged, list_accounts_under, list_incomplete_accounts, list_attachments, release_vault_lock, retrieve_account, search_by_password_content, list_expired, master_exists, set_audit_exclusion, set_expiry, set_generation_policy, update_account, update_master, username_usage_report, vacuum_db, verify_master, Account, Category, AccountSummary, Attachment, Master}, encryption::{combine_with_keyfile, decrypt_bytes, encrypt_bytes, encrypt_password, hash_master_password, key_fingerprint, master_password_candidates, normalize_master_password, try_decrypt_password, KeyFileDigest}, pass_store::{ImportCandidate, ReviewChoice}, generator::{describe_password, generate_password, PasswordOptions, AMBIGUOUS}, os_keyring::{delete_master_from_keyring, fetch_master_from_keyring, store_master_in_keyring}, password_policy::{validate_master_strength, MasterPasswordPolicy}, terminal::{discard_pending_input, reveal_for, terminal_width, TerminalState}};
 
-fn print_separator() {
+pub fn print_separator() {
     println!("------------------------------");
 }
 fn display_main_menu() {
     println!("==============================");
-    println!("Password Manager:");
-    println!("1. Add an account");
-    println!("2. List accounts");
-    println!("3. Retrieve an account");
-    println!("4. Update an account");
-    println!("5. Delete an account");
-    println!("6. Change master password");
-    println!("x. Exit");
+    println!("{}", t("menu.title"));
+    println!("{}", t("menu.add_account"));
+    println!("{}", t("menu.list_accounts"));
+    println!("{}", t("menu.get_account"));
+    println!("{}", t("menu.update_account"));
+    println!("{}", t("menu.delete_account"));
+    println!("{}", t("menu.change_master_password"));
+    println!("{}", t("menu.rename_master"));
+    println!("{}", t("menu.attachments"));
+    println!("{}", t("menu.incomplete_accounts"));
+    println!("{}", t("menu.generate_password"));
+    println!("{}", t("menu.review_date"));
+    println!("{}", t("menu.quick_add"));
+    println!("{}", t("menu.search_password"));
+    println!("{}", t("menu.key_fingerprint"));
+    println!("{}", t("menu.duplicate_account"));
+    println!("{}", t("menu.whoami"));
+    println!("{}", t("menu.list_namespace"));
+    println!("{}", t("menu.show_username"));
+    println!("{}", t("menu.audit_exclusion"));
+    println!("{}", t("menu.username_usage"));
+    println!("{}", t("menu.vacuum"));
+    println!("{}", t("menu.exit"));
+    println!("{}", t_with("menu.cancel_hint", &[("keyword", &CANCEL_KEYWORD)]));
+}
+
+/// Digest of the key file passed with --keyfile, set once at startup
+static KEY_FILE: OnceLock<KeyFileDigest> = OnceLock::new();
+
+/// Makes the key file available to master logins and setup
+pub fn set_keyfile(keyfile: KeyFileDigest) {
+    let _ = KEY_FILE.set(keyfile);
+}
+
+/// Vault identifier for OS keyring entries, only set with --use-keyring
+static KEYRING_VAULT: OnceLock<String> = OnceLock::new();
+/// Storing in the keyring is offered at most once per run
+static KEYRING_OFFERED: AtomicBool = AtomicBool::new(false);
+
+/// Master that logged in when the menu was opened, the first successful login of the run
+static SESSION_MASTER: OnceLock<i64> = OnceLock::new();
+
+/// Set by --no-retry, a failed master login exits right away
+static NO_LOGIN_RETRY: AtomicBool = AtomicBool::new(false);
+
+/// Makes a wrong master password or a database error end the run instead of prompting again
+pub fn disable_login_retry() {
+    NO_LOGIN_RETRY.store(true, Ordering::Relaxed);
+}
+
+/// Lets master logins use the OS keyring, `vault` tells vaults apart (the database path)
+pub fn enable_keyring(vault: String) {
+    let _ = KEYRING_VAULT.set(vault);
 }
 
 pub async fn start_ui_loop(pool: &SqlitePool) {
-    let _result = obtain_master_credentials(pool).await;
+    // Logging in can never succeed without a master, so set one up first
+    match master_exists(pool).await {
+        Ok(true) => {}
+        Ok(false) => {
+            println!("No vault initialized yet, a master account needs to be set up first.");
+            match handle_master_setup(pool).await {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(err) => {
+                    report_input_error(&err);
+                    return;
+                }
+            }
+        }
+        Err(err) => {
+            println!("Failed to read master accounts: {}", err);
+            return;
+        }
+    }
+
+    // Vaults set up by older versions start with a default master password
+    let login_result = match is_default_master(pool).await {
+        Ok(true) => {
+            println!("Warning: a master account still uses the default or an empty password.");
+            println!("Anyone with the database file can read your passwords until it is changed.");
+            match confirm("Change it now?") {
+                // Changing the password requires logging in, which also logs in for the menu
+                Ok(true) => handle_change_master_password(pool).await,
+                Ok(false) => obtain_master_credentials(pool).await.map(|_| ()),
+                Err(err) => Err(err),
+            }
+        }
+        _ => obtain_master_credentials(pool).await.map(|_| ()),
+    };
+    if let Err(err) = login_result {
+        report_input_error(&err);
+        return;
+    }
+    print_login_preview(pool).await;
+    print_review_reminders(pool).await;
+    // Option to run again on an empty choice, only kept with REPEAT_LAST_ACTION
+    let mut last_choice: Option<String> = None;
     loop {
         display_main_menu();
 
-        print!("Please choose an option: ");
-        let user_choice = get_user_input();
+        match &last_choice {
+            Some(last) => print!("{}", t_with("menu.choose_repeat", &[("choice", last)])),
+            None => print!("{}", t("menu.choose")),
+        }
+        let user_choice = match get_user_input() {
+            Ok(choice) if choice.is_empty() => last_choice.clone().unwrap_or(choice),
+            Ok(choice) => choice,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                // Stdin is closed (Ctrl-D), nothing more can be read
+                println!();
+                println!("{}", t("menu.exiting"));
+                break;
+            }
+            Err(err) => {
+                report_input_error(&err);
+                continue;
+            }
+        };
         println!("==============================");
 
-        match user_choice.as_str() {
-            "1" => {
-                handle_add_account(pool).await;
+        let result = match user_choice.as_str() {
+            "1" => handle_add_account(pool, PROMPT_OPTIONAL_FIELDS).await,
+            "2" => handle_list_accounts(pool).await,
+            "3" => handle_get_account(pool).await,
+            "4" => handle_update_account(pool).await,
+            "5" => handle_delete_account(pool).await,
+            "6" => handle_change_master_password(pool).await,
+            "7" => handle_rename_master(pool).await,
+            "8" => handle_attachments(pool).await,
+            "9" => handle_list_incomplete_accounts(pool).await,
+            "10" => handle_generate_account_password(pool).await,
+            "11" => handle_set_review_date(pool).await,
+            "12" => handle_add_account(pool, false).await,
+            "13" => handle_search_password_content(pool).await,
+            "14" => handle_show_key_fingerprint(pool).await,
+            "15" => handle_duplicate_account(pool).await,
+            "16" => handle_whoami(pool).await,
+            "17" => handle_list_namespace(pool).await,
+            "18" => handle_show_username(pool).await,
+            "19" => handle_toggle_audit_exclusion(pool).await,
+            "20" => handle_username_usage(pool).await,
+            "21" => handle_vacuum(pool).await,
+            "x" => {
+                println!("{}", t("menu.exiting"));
+                break;
             }
-            "2" => {
-                handle_list_accounts(pool).await;
+            _ => {
+                println!("{}", t("menu.invalid_option"));
+                continue;
             }
-            "3" => {
-                handle_get_account(pool).await;
+        };
+        if REPEAT_LAST_ACTION {
+            last_choice = Some(user_choice);
+        }
+
+        // A failed read cancels the current operation and returns to the menu
+        if let Err(err) = result {
+            report_input_error(&err);
+        }
+    }
+
+    backup_on_exit(pool).await;
+}
+
+/// Backs up the vault into AUTO_BACKUP_DIR if it is set, after leaving the menu
+async fn backup_on_exit(pool: &SqlitePool) {
+    let Some(dir) = AUTO_BACKUP_DIR else {
+        return;
+    };
+    // An --ephemeral vault has no file, it is saved with --save-to instead
+    if db_file_path(pool).await.is_err() {
+        return;
+    }
+
+    match auto_backup(pool, Path::new(dir), AUTO_BACKUP_KEEP).await {
+        Ok((backup_path, removed)) => {
+            println!("Backed up the vault to {}", backup_path.display());
+            if !removed.is_empty() {
+                println!("Removed {} older backup(s), keeping the newest {}.", removed.len(), AUTO_BACKUP_KEEP);
             }
-            "4" => {
-                handle_update_account(pool).await;
+        }
+        Err(err) => println!("Automatic backup failed: {:#}", err),
+    }
+}
+
+/// Typed at any prompt except a password prompt to abort the current operation
+const CANCEL_KEYWORD: &str = "cancel";
+
+/// Reads a trimmed line from stdin, for everything except passwords
+///
+/// Returns an [`io::ErrorKind::UnexpectedEof`] error when stdin is closed (Ctrl-D),
+/// or an [`io::ErrorKind::Interrupted`] error when [`CANCEL_KEYWORD`] is typed.
+/// The handlers treat both as cancelling the current operation, nothing is saved
+fn get_user_input() -> io::Result<String> {
+    let input = read_line()?.trim().to_string();
+    if input.eq_ignore_ascii_case(CANCEL_KEYWORD) {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled by user"));
+    }
+    Ok(input)
+}
+
+/// Reads a line from stdin with only the line ending removed
+fn read_line() -> io::Result<String> {
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "End of input"));
+    }
+
+    strip_line_ending(&mut input);
+    Ok(input)
+}
+
+/// Removes a single trailing `\n` or `\r\n`, anything before it is part of the input
+fn strip_line_ending(input: &mut String) {
+    if input.ends_with('\n') {
+        input.pop();
+        if input.ends_with('\r') {
+            input.pop();
+        }
+    }
+}
+
+/// Reads a password exactly as typed
+/// 
+/// Passwords are never trimmed, leading and trailing spaces can be part of a valid password.
+/// rpassword only strips the line ending as well
+/// 
+/// Returns an [`io::ErrorKind::InvalidInput`] error when more than one line was pasted,
+/// the extra lines are discarded so they don't end up as answers to the next prompts
+fn get_password() -> io::Result<String> {
+    // Never echo passwords in release builds, whatever the config says
+    if PLAIN_PASSWORD_INPUT && cfg!(debug_assertions) {
+        return read_line();
+    }
+
+    io::stdout().flush()?;
+    let mut password = rpassword::read_password()?;
+    if discard_pending_input() {
+        password.zeroize();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Password input contained more than one line, possibly a paste error",
+        ));
+    }
+    Ok(password)
+}
+
+/// Asks a yes/no question, anything other than y/yes counts as no
+pub fn confirm(prompt: &str) -> io::Result<bool> {
+    print!("{} [y/N]: ", prompt);
+    let answer = get_user_input()?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Reads the master password, giving up after [`MASTER_PROMPT_TIMEOUT_SECS`] if it is set
+/// 
+/// Returns an [`io::ErrorKind::TimedOut`] error on timeout. The abandoned read can't be
+/// cancelled and keeps waiting on the terminal, so the caller must not prompt again
+fn get_master_password() -> io::Result<String> {
+    let Some(timeout_secs) = MASTER_PROMPT_TIMEOUT_SECS else {
+        return get_password();
+    };
+
+    let terminal_state = TerminalState::save();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(get_password());
+    });
+
+    match receiver.recv_timeout(Duration::from_secs(timeout_secs)) {
+        Ok(result) => result,
+        Err(_) => {
+            // The reader thread still holds the terminal in raw mode
+            if let Some(state) = terminal_state {
+                state.restore();
             }
-            "5" => {
-                handle_delete_account(pool).await;
+            Err(io::Error::new(io::ErrorKind::TimedOut, "Master password prompt timed out"))
+        }
+    }
+}
+
+/// Asks for the master password that unlocks the SQLCipher database file
+/// 
+/// Returns the forms to try the key in, see [`master_password_candidates()`]
+#[cfg(feature = "sqlcipher")]
+pub fn prompt_database_key() -> io::Result<Vec<String>> {
+    print!("Enter master password to unlock the database: ");
+    let mut password = get_password()?;
+    let candidates = master_password_candidates(&password);
+    password.zeroize();
+    Ok(candidates)
+}
+
+fn report_input_error(err: &io::Error) {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            println!();
+            println!("Operation cancelled.");
+        }
+        io::ErrorKind::Interrupted => println!("Operation cancelled."),
+        io::ErrorKind::InvalidData => println!("Input was not valid UTF-8, operation cancelled."),
+        io::ErrorKind::InvalidInput => println!("{}, operation cancelled.", err),
+        _ => println!("Failed to read input: {}", err),
+    }
+}
+
+/// Adds an account, `ask_optional` controls whether the URL, description, login steps and change-password URL are asked for
+async fn handle_add_account(pool: &SqlitePool, ask_optional: bool) -> io::Result<()> {
+    println!("Enter account name (ie. Google, X, Discord): ");
+    let name = get_user_input()?;
+
+    let url = if ask_optional {
+        println!("(Optional) Enter url for account (ie. google.com, x.com, login.live.com): ");
+        let url_input = get_user_input()?;
+        // If the user enters an empty string, set url to None
+        if url_input.is_empty() { None } else { Some(url_input) }
+    } else {
+        None
+    };
+
+    println!("Enter username: ");
+    let username = get_user_input()?;
+
+    println!("Enter password: ");
+    let mut password = get_password()?;
+
+    let description = if ask_optional {
+        println!("(Optional) Enter description for account: ");
+        let description_input = get_user_input()?;
+        // If the user enters an empty string, set description to None
+        if description_input.is_empty() { None } else { Some(description_input) }
+    } else {
+        None
+    };
+
+    // Sensitive notes are encrypted, others stay plaintext so they show up in listings
+    let encrypted_note = description.is_some() && confirm("Is the description sensitive? It will be encrypted")?;
+
+    let login_steps = if ask_optional {
+        println!("(Optional) Enter login steps (ie. enter username, click Next, then password): ");
+        let steps_input = get_user_input()?;
+        if steps_input.is_empty() { None } else { Some(steps_input) }
+    } else {
+        None
+    };
+    let encrypted_login_steps = login_steps.is_some() && confirm("Are the login steps sensitive? They will be encrypted")?;
+
+    let change_password_url = if ask_optional {
+        println!("(Optional) Enter the URL of the site's change-password page: ");
+        let change_url_input = get_user_input()?;
+        if change_url_input.is_empty() { None } else { Some(change_url_input) }
+    } else {
+        None
+    };
+
+    let category = if ask_optional {
+        prompt_category("(Optional) Enter category (personal, work, shared), leave empty for personal:", Category::default())?
+    } else {
+        Category::default()
+    };
+
+    let master = obtain_master_credentials(pool).await?;
+
+    // Warn about reuse before anything is saved
+    loop {
+        let reused_by = match find_accounts_using_password(pool, &master.password, &password).await {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                println!("Failed to check for reused passwords: {}", err);
+                break;
             }
-            "6" => {
-                handle_change_master_password(pool).await;
+        };
+        if reused_by.is_empty() {
+            break;
+        }
+
+        for account in &reused_by {
+            println!("This password is already used by account {} (ID {}).", account.name, account.id);
+        }
+        if confirm("Use it anyway?")? {
+            break;
+        }
+        password.zeroize();
+        println!("Enter a different password: ");
+        password = get_password()?;
+    }
+
+    // Encrypt password before adding
+    let encrypted_password = encrypt_password(&master.password, &password);
+    password.zeroize();
+    let description = if encrypted_note {
+        description.map(|description| encrypt_password(&master.password, &description))
+    } else {
+        description
+    };
+
+    let login_steps = if encrypted_login_steps {
+        login_steps.map(|steps| encrypt_password(&master.password, &steps))
+    } else {
+        login_steps
+    };
+
+    let mut account = Account::new(name, username, encrypted_password, url, description);
+    account.encrypted_note = encrypted_note;
+    account.login_steps = login_steps;
+    account.encrypted_login_steps = encrypted_login_steps;
+    account.change_password_url = change_password_url;
+    account.category = category;
+
+    if let Err(err) = add_account(pool, &account).await {
+        println!("Failed to add account: {}", err);
+    }
+    Ok(())
+}
+
+pub fn print_account_summary_details(account: &AccountSummary) {
+    println!("Account ID: {}", account.id);
+    println!("Name: {}", account.name);
+    if let Some(namespace) = account.namespace() {
+        println!("Namespace: {}", namespace);
+    }
+    match &account.description {
+        Some(desc) => print_field("Description", desc),
+        None if account.encrypted_note => println!("Description: (encrypted)"),
+        None => println!("Description: N/A"),
+    }
+}
+
+/// Prints every field of an account, with `show_password` false the password is left out (ie. to reveal it separately)
+pub fn print_account_details(account: &Account, master_password: &String, show_password: bool) {
+    println!("Account Details:");
+    println!("ID: {}", account.id);
+    print_field("Name", &account.name);
+    // Shown before the credentials, they say how to use them
+    match &account.login_steps {
+        Some(steps) if account.encrypted_login_steps => print_encrypted_field("Login steps", steps, master_password),
+        Some(steps) => print_field("Login steps", steps),
+        None => {}
+    }
+    print_field("Username", &account.username);
+
+    // Decrypt password before showing
+    // Never wrapped, a line break would end up in a copied password
+    if show_password {
+        match try_decrypt_password(master_password, &account.password) {
+            Ok(mut decrypted_password) => {
+                println!("Password: {}", decrypted_password);
+                decrypted_password.zeroize();
             }
-            "x" => {
-                println!("Exiting...");
-                break;
+            Err(_) => println!("Password: {}", UNREADABLE_FIELD),
+        }
+    } else {
+        println!("Password: (hidden)");
+    }
+    match &account.url {
+        Some(url) => print_field("URL", url),
+        None => println!("URL: N/A"),
+    }
+    if let Some(change_password_url) = &account.change_password_url {
+        print_field("Change password at", change_password_url);
+    }
+    match &account.description {
+        // Sensitive notes are encrypted like the password
+        Some(description) if account.encrypted_note => print_encrypted_field("Description", description, master_password),
+        Some(description) => print_field("Description", description),
+        None => println!("Description: N/A"),
+    }
+    println!("Category: {}", account.category.as_str());
+}
+
+/// Shown instead of an encrypted field that doesn't decrypt with the logged-in master password
+const UNREADABLE_FIELD: &str = "(could not be decrypted, it may belong to another master account)";
+
+/// Decrypts and prints an encrypted field, or says it can't be decrypted instead of stopping the program
+fn print_encrypted_field(label: &str, value: &str, master_password: &String) {
+    match try_decrypt_password(master_password, value) {
+        Ok(mut plaintext) => {
+            print_field(label, &plaintext);
+            plaintext.zeroize();
+        }
+        Err(_) => println!("{}: {}", label, UNREADABLE_FIELD),
+    }
+}
+
+/// Prints `label: value`, wrapping a long value to the terminal width
+fn print_field(label: &str, value: &str) {
+    println!("{}", wrap_field(label, value, terminal_width()));
+}
+
+/// Wraps `value` at word boundaries, continuation lines are indented to line up under the value
+/// 
+/// Line breaks in the value are kept, lines that already fit are left untouched,
+/// and words longer than a line (ie. URLs) are split
+fn wrap_field(label: &str, value: &str, width: usize) -> String {
+    let prefix = format!("{}: ", label);
+    let indent = prefix.chars().count();
+    // Long labels on narrow terminals still get some room per line
+    let room = width.saturating_sub(indent).max(20);
+
+    let mut lines: Vec<String> = Vec::new();
+    for paragraph in value.lines() {
+        if paragraph.chars().count() <= room {
+            lines.push(paragraph.to_string());
+            continue;
+        }
+
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            let line_len = line.chars().count();
+            if line_len > 0 && line_len + 1 + word.len() <= room {
+                line.push(' ');
+                line.extend(&word);
+                continue;
+            }
+            if line_len > 0 {
+                lines.push(std::mem::take(&mut line));
+            }
+            while word.len() > room {
+                lines.push(word.drain(..room).collect());
+            }
+            line.extend(&word);
+        }
+        lines.push(line);
+    }
+
+    let separator = format!("\n{}", " ".repeat(indent));
+    format!("{}{}", prefix, lines.join(&separator))
+}
+
+/// Prints account summaries under a header per category, empty categories are left out
+/// 
+/// Each account gets a health marker when `health` is given
+pub fn print_grouped_accounts(groups: &BTreeMap<Category, Vec<AccountSummary>>, health: Option<&HashMap<i64, AccountHealth>>) {
+    let color = io::stdout().is_terminal();
+    for (category, accounts) in groups {
+        println!("=== {} ({}) ===", category.as_str(), accounts.len());
+        for account in accounts {
+            print_account_summary_details(account);
+            if let Some(account_health) = health.and_then(|health| health.get(&account.id)) {
+                println!("Health: {}", account_health.markers(color));
+            }
+            print_separator();
+        }
+    }
+}
+
+async fn handle_list_accounts(pool: &SqlitePool) -> io::Result<()> {
+    println!("Listing accounts: ");
+
+    match list_accounts_grouped(pool).await {
+        Ok(groups) if groups.is_empty() => println!("No accounts stored yet. Use option 1 to add one."),
+        Ok(groups) => print_grouped_accounts(&groups, None),
+        Err(err) => {
+            println!("Failed to list accounts: {}", err);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_list_namespace(pool: &SqlitePool) -> io::Result<()> {
+    println!("Enter namespace (ie. work for work/github):");
+    let prefix = get_user_input()?;
+    if prefix.is_empty() {
+        println!("No namespace entered.");
+        return Ok(());
+    }
+
+    match list_accounts_under(pool, &prefix).await {
+        Ok(results) if results.is_empty() => println!("No accounts in namespace {}.", prefix),
+        Ok(results) => {
+            for account in results {
+                print_account_summary_details(&account);
+                print_separator();
+            }
+        },
+        Err(err) => {
+            println!("Failed to list accounts: {}", err);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_list_incomplete_accounts(pool: &SqlitePool) -> io::Result<()> {
+    println!("Accounts with missing or inconsistent details: ");
+
+    match list_incomplete_accounts(pool).await {
+        Ok(results) if results.is_empty() => println!("None, every account passed the checks."),
+        Ok(results) => {
+            for account in results {
+                print_account_summary_details(&account);
+                print_separator();
+            }
+        },
+        Err(err) => {
+            println!("Failed to list accounts: {}", err);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_search_password_content(pool: &SqlitePool) -> io::Result<()> {
+    if !confirm("This decrypts every stored password to search them. Continue?")? {
+        return Ok(());
+    }
+
+    let master = obtain_master_credentials(pool).await?;
+
+    println!("Enter text to look for (input hidden):");
+    let mut substring = get_password()?;
+    if substring.is_empty() {
+        println!("Nothing to search for.");
+        return Ok(());
+    }
+
+    let results = search_by_password_content(pool, &master.password, &substring).await;
+    substring.zeroize();
+
+    match results {
+        Ok(results) if results.is_empty() => println!("No passwords contain that text."),
+        Ok(results) => {
+            println!("Accounts whose password contains that text: ");
+            for account in results {
+                print_account_summary_details(&account);
+                print_separator();
+            }
+        },
+        Err(err) => {
+            println!("Failed to search accounts: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// The fingerprint stays the same as long as the same master password (and key file) is used for this master
+async fn handle_show_key_fingerprint(pool: &SqlitePool) -> io::Result<()> {
+    let master = obtain_master_credentials(pool).await?;
+
+    let fingerprint = get_master_by_username(pool, &master.username)
+        .await
+        .and_then(|stored| key_fingerprint(&master.password, &stored.password));
+    match fingerprint {
+        Ok(fingerprint) => println!("Master key fingerprint: {}", fingerprint),
+        Err(err) => {
+            println!("Failed to compute the fingerprint: {}", err);
+            return Ok(());
+        }
+    }
+    println!("Compare it with an earlier session to confirm the same master password was entered.");
+    Ok(())
+}
+
+/// Shows who is logged in without asking for the password again
+/// 
+/// The master is looked up by ID, so a rename during the session is reflected
+async fn handle_whoami(pool: &SqlitePool) -> io::Result<()> {
+    let Some(id) = SESSION_MASTER.get() else {
+        println!("No master is logged in.");
+        return Ok(());
+    };
+    match get_master_by_id(pool, *id).await {
+        Ok(master) => println!("Logged in as: {}", master.username),
+        Err(err) => {
+            println!("Failed to read the logged-in master: {}", err);
+            return Ok(());
+        }
+    }
+
+    match count_accounts(pool).await {
+        Ok(total) => println!("Accounts in this vault: {}", total),
+        Err(err) => println!("Failed to count accounts: {}", err),
+    }
+    if !SINGLE_MASTER_FLAG {
+        println!("Accounts added by other masters are listed too, but only open with their master password.");
+    }
+    Ok(())
+}
+
+/// Shows an import candidate, never its password, and asks whether to include, skip or edit it
+pub fn review_import_candidate(candidate: &mut ImportCandidate) -> io::Result<ReviewChoice> {
+    loop {
+        print_separator();
+        print_field("Name", &candidate.name);
+        print_field("Username", &candidate.username);
+        match &candidate.url {
+            Some(url) => print_field("URL", url),
+            None => println!("URL: N/A"),
+        }
+        match &candidate.notes {
+            Some(_) => println!("Notes: yes, kept as a sensitive note"),
+            None => println!("Notes: N/A"),
+        }
+
+        print!("[i]nclude, [s]kip, [e]dit or include [a]ll remaining: ");
+        match get_user_input()?.to_lowercase().as_str() {
+            "i" | "include" => return Ok(ReviewChoice::Include),
+            "s" | "skip" => return Ok(ReviewChoice::Skip),
+            "a" | "all" => return Ok(ReviewChoice::IncludeAll),
+            "e" | "edit" => {
+                println!("Enter the new name (leave empty to keep current):");
+                let name = get_user_input()?;
+                if !name.is_empty() {
+                    candidate.name = name;
+                }
+                println!("Enter the new username (leave empty to keep current):");
+                let username = get_user_input()?;
+                if !username.is_empty() {
+                    candidate.username = username;
+                }
+                println!("Enter the new URL (\"none\" to clear, leave empty to keep current):");
+                let url = get_user_input()?;
+                if url.eq_ignore_ascii_case("none") {
+                    candidate.url = None;
+                } else if !url.is_empty() {
+                    candidate.url = Some(url);
+                }
+            }
+            _ => println!("Invalid choice, please try again."),
+        }
+    }
+}
+
+/// Answers "which username did I use here?" without the master password, the password is never decrypted
+async fn handle_show_username(pool: &SqlitePool) -> io::Result<()> {
+    let Some(account) = select_account(pool, "Select an account").await? else {
+        return Ok(());
+    };
+
+    print_account_login(&account);
+    Ok(())
+}
+
+/// Reduced view of an account: name, username and URL, nothing encrypted
+pub fn print_account_login(account: &Account) {
+    print_field("Name", &account.name);
+    print_field("Username", &account.username);
+    match &account.url {
+        Some(url) => print_field("URL", url),
+        None => println!("URL: N/A"),
+    }
+}
+
+async fn handle_get_account(pool: &SqlitePool) -> io::Result<()> {
+    let Some(account) = select_account(pool, "Select an account to retrieve").await? else {
+        return Ok(());
+    };
+
+    let master = obtain_master_credentials(pool).await?;
+    let account = match retrieve_account(pool, account.id).await {
+        Ok(account) => account,
+        Err(err) => {
+            println!("Error fetching account: {}", err);
+            return Ok(());
+        }
+    };
+
+    // The countdown rewrites its line in place, which only works on a terminal
+    if !TIMED_PASSWORD_REVEAL || !io::stdout().is_terminal() {
+        print_account_details(&account, &master.password, true);
+        return Ok(());
+    }
+    print_account_details(&account, &master.password, false);
+    let Ok(mut password) = try_decrypt_password(&master.password, &account.password) else {
+        println!("Password: {}", UNREADABLE_FIELD);
+        return Ok(());
+    };
+    let revealed = reveal_for("Password", &password, PASSWORD_REVEAL_SECS);
+    password.zeroize();
+    revealed
+}
+
+/// Destructive actions that can be set to ask for the master password again
+#[derive(Clone, Copy)]
+enum SensitiveAction {
+    DeleteAccount,
+    DeleteAttachment,
+}
+
+/// Whether `action` asks for the master password first, see the REAUTH_* settings in compile_config.rs
+fn require_reauth_for(action: SensitiveAction) -> bool {
+    match action {
+        SensitiveAction::DeleteAccount => REAUTH_DELETE_ACCOUNT,
+        SensitiveAction::DeleteAttachment => REAUTH_DELETE_ATTACHMENT,
+    }
+}
+
+async fn handle_delete_account(pool: &SqlitePool) -> io::Result<()> {
+    let Some(account) = select_account(pool, "Select an account to delete").await? else {
+        return Ok(());
+    };
+
+    if require_reauth_for(SensitiveAction::DeleteAccount) {
+        println!("Confirm with the master password to delete {}:", account.name);
+        reauthenticate(pool).await?;
+    }
+
+    if let Err(err) = delete_account_by_id(pool, account.id).await {
+        println!("Error deleting account: {}", err);
+    }
+    Ok(())
+}
+
+async fn handle_update_account(pool: &SqlitePool) -> io::Result<()> {
+    let Some(mut account) = select_account(pool, "Select an account to update").await? else {
+        return Ok(());
+    };
+
+    update_account_details(pool, &mut account).await
+}
+
+/// Asks which account to act on
+/// 
+/// On a terminal this is a fuzzy picker over the account names, otherwise (ie. piped input)
+/// the ID or name is typed in. Returns None when nothing was picked or nothing matched
+async fn select_account(pool: &SqlitePool, prompt: &str) -> io::Result<Option<Account>> {
+    if !(io::stdin().is_terminal() && io::stderr().is_terminal()) {
+        println!("Enter account ID or name:");
+        let user_input = get_user_input()?;
+
+        // Automatically determine if id or name
+        let account = match user_input.parse::<i64>() {
+            Ok(id) => get_account_by_id(pool, id).await,
+            Err(_) => get_account_by_name(pool, &user_input).await,
+        };
+        match &account {
+            Err(err) if !is_not_found(err) => println!("{}", err),
+            Err(_) => println!("No account found matching: {}", user_input),
+            Ok(_) => {}
+        }
+        return Ok(account.ok());
+    }
+
+    let summaries = match list_accounts(pool).await {
+        Ok(summaries) => summaries,
+        Err(err) => {
+            println!("Failed to list accounts: {}", err);
+            return Ok(None);
+        }
+    };
+    if summaries.is_empty() {
+        println!("No accounts stored yet.");
+        return Ok(None);
+    }
+
+    // Type to filter, arrow keys to move, Esc to cancel
+    let items: Vec<String> = summaries.iter().map(|account| format!("{} (ID {})", account.name, account.id)).collect();
+    let picked = FuzzySelect::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(0)
+        .interact_opt()
+        .map_err(|dialoguer::Error::IO(err)| err)?;
+    let Some(index) = picked else {
+        println!("Nothing selected.");
+        return Ok(None);
+    };
+
+    match get_account_by_id(pool, summaries[index].id).await {
+        Ok(account) => Ok(Some(account)),
+        Err(err) => {
+            println!("Error fetching account: {}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// Copies an account as a starting point for a similar one, ie. another login on the same provider
+async fn handle_duplicate_account(pool: &SqlitePool) -> io::Result<()> {
+    let Some(account) = select_account(pool, "Select an account to duplicate").await? else {
+        return Ok(());
+    };
+
+    println!("Enter a name for the copy:");
+    let new_name = get_user_input()?;
+    if new_name.is_empty() {
+        println!("Name can't be empty.");
+        return Ok(());
+    }
+    if get_account_by_name(pool, &new_name).await.is_ok() {
+        println!("An account named {} already exists.", new_name);
+        return Ok(());
+    }
+
+    let new_id = match clone_account(pool, account.id, &new_name).await {
+        Ok(new_id) => new_id,
+        Err(err) => {
+            println!("Failed to duplicate account: {}", err);
+            return Ok(());
+        }
+    };
+    println!("Created {} (ID {}) as a copy of {}.", new_name, new_id, account.name);
+
+    if confirm("Edit the copy now?")? {
+        match get_account_by_id(pool, new_id).await {
+            Ok(mut copy) => update_account_details(pool, &mut copy).await?,
+            Err(err) => println!("Failed to read the copy: {}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Helper function for handle_update_account()
+async fn update_account_details(pool: &SqlitePool, account: &mut Account) -> io::Result<()> {
+    println!("\nCurrent account details:");
+    println!("Name: {}", account.name);
+    println!("Username: {}", account.username);
+    if let Some(url) = &account.url {
+        println!("URL: {}", url);
+    } else {
+        println!("URL: N/A");
+    }
+    if let Some(change_password_url) = &account.change_password_url {
+        println!("Change password URL: {}", change_password_url);
+    } else {
+        println!("Change password URL: N/A");
+    }
+    match &account.description {
+        Some(_) if account.encrypted_note => println!("Description: (encrypted)"),
+        Some(description) => println!("Description: {}", description),
+        None => println!("Description: N/A"),
+    }
+    match &account.login_steps {
+        Some(_) if account.encrypted_login_steps => println!("Login steps: (encrypted)"),
+        Some(steps) => println!("Login steps: {}", steps),
+        None => println!("Login steps: N/A"),
+    }
+    println!("Category: {}", account.category.as_str());
+
+    // Step 3: Ask for new values
+    println!("\nEnter the new account name (leave empty to keep current):");
+    let name = get_user_input()?;
+    let name = if name.is_empty() { account.name.clone() } else { name };
+
+    println!("Enter the new username (leave empty to keep current):");
+    let username = get_user_input()?;
+    let username = if username.is_empty() { account.username.clone() } else { username };
+
+    println!("Enter the new password (leave empty to keep current):");
+    let password = get_password()?;
+
+    println!("Enter the new URL (leave empty to keep current):");
+    let url = get_user_input()?;
+    let url = if url.is_empty() { account.url.clone() } else { Some(url) };
+
+    println!("Enter the new change-password URL (leave empty to keep current):");
+    let change_password_url = get_user_input()?;
+    let change_password_url = if change_password_url.is_empty() { account.change_password_url.clone() } else { Some(change_password_url) };
+
+    println!("Enter the new description (leave empty to keep current):");
+    let new_description = get_user_input()?;
+    let encrypted_note = prompt_encrypt_field("the description as a sensitive note", &new_description, &account.description, account.encrypted_note)?;
+
+    println!("Enter the new login steps (leave empty to keep current):");
+    let new_login_steps = get_user_input()?;
+    let encrypted_login_steps = prompt_encrypt_field("the login steps", &new_login_steps, &account.login_steps, account.encrypted_login_steps)?;
+
+    let category = prompt_category("Enter the new category (personal, work, shared), leave empty to keep current:", account.category)?;
+
+    // A new password or an encrypted field that changes needs the master password
+    let note_changed = !new_description.is_empty() || encrypted_note != account.encrypted_note;
+    let steps_changed = !new_login_steps.is_empty() || encrypted_login_steps != account.encrypted_login_steps;
+    let master = if !password.is_empty()
+        || (note_changed && (encrypted_note || account.encrypted_note))
+        || (steps_changed && (encrypted_login_steps || account.encrypted_login_steps))
+    {
+        Some(obtain_master_credentials(pool).await?)
+    } else {
+        None
+    };
+
+    let encrypted_password = match &master {
+        Some(master) if !password.is_empty() => encrypt_password(&master.password, &password),
+        // The stored password is already encrypted, only a new one needs encrypting
+        _ => account.password.clone(),
+    };
+
+    // An encrypted field from another master can't be re-encrypted, nothing is saved then
+    let description = match updated_protected_field(master.as_ref(), new_description, &account.description, account.encrypted_note, encrypted_note) {
+        Ok(description) => description,
+        Err(_) => {
+            println!("The description could not be decrypted, it may belong to another master account. The account was not updated.");
+            return Ok(());
+        }
+    };
+    let login_steps = match updated_protected_field(master.as_ref(), new_login_steps, &account.login_steps, account.encrypted_login_steps, encrypted_login_steps) {
+        Ok(login_steps) => login_steps,
+        Err(_) => {
+            println!("The login steps could not be decrypted, it may belong to another master account. The account was not updated.");
+            return Ok(());
+        }
+    };
+
+    let updated_account = Account {
+        id: account.id, // Keep the same ID
+        name,
+        username,
+        password: encrypted_password,
+        url,
+        description,
+        encrypted_note,
+        login_steps,
+        encrypted_login_steps,
+        change_password_url,
+        category,
+    };
+
+    match update_account(pool, &updated_account).await {
+        Ok(_) => {
+            println!("Account with ID {} was updated successfully.", updated_account.id);
+        }
+        Err(e) => {
+            println!("Failed to update account with ID {}: {:?}", updated_account.id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Asks for a [`Category`] until a valid one is entered, empty input keeps `default`
+fn prompt_category(prompt: &str, default: Category) -> io::Result<Category> {
+    loop {
+        println!("{}", prompt);
+        let input = get_user_input()?;
+        if input.is_empty() {
+            return Ok(default);
+        }
+        match input.parse() {
+            Ok(category) => return Ok(category),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+/// Asks whether a text field should be stored encrypted, only when there is a value to protect
+/// 
+/// Helper function for update_account_details()
+fn prompt_encrypt_field(field: &str, new_value: &str, current: &Option<String>, currently_encrypted: bool) -> io::Result<bool> {
+    if new_value.is_empty() && current.is_none() {
+        return Ok(false);
+    }
+
+    let current_choice = if currently_encrypted { "yes" } else { "no" };
+    println!("Encrypt {}? (y/n, leave empty to keep {}):", field, current_choice);
+    Ok(match get_user_input()?.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => currently_encrypted,
+    })
+}
+
+/// Stored value of an optionally encrypted text field after an update
+/// 
+/// `master` is only needed when the field changes and is or was encrypted.
+/// Returns an error if the current value has to be decrypted and doesn't decrypt with it
+/// 
+/// Helper function for update_account_details()
+fn updated_protected_field(master: Option<&MasterCredentials>, new_value: String, current: &Option<String>, was_encrypted: bool, encrypt: bool) -> anyhow::Result<Option<String>> {
+    let changed = !new_value.is_empty() || encrypt != was_encrypted;
+
+    match master {
+        Some(master) if changed => {
+            // Start from the plaintext value, then encrypt it if it stays sensitive
+            let value = if !new_value.is_empty() {
+                Some(new_value)
+            } else if was_encrypted {
+                current.as_deref().map(|value| try_decrypt_password(&master.password, value)).transpose()?
+            } else {
+                current.clone()
+            };
+
+            if encrypt {
+                Ok(value.map(|mut value| {
+                    let encrypted = encrypt_password(&master.password, &value);
+                    value.zeroize();
+                    encrypted
+                }))
+            } else {
+                Ok(value)
+            }
+        }
+        _ if new_value.is_empty() => Ok(current.clone()),
+        _ => Ok(Some(new_value)),
+    }
+}
+
+/// Generates a new password for an account following its generation policy, and offers to save it
+/// 
+/// The policy is kept per account, for sites that restrict length or characters
+async fn handle_generate_account_password(pool: &SqlitePool) -> io::Result<()> {
+    let Some(mut account) = select_account(pool, "Select an account to generate a password for").await? else {
+        return Ok(());
+    };
+
+    let mut policy = match get_generation_policy(pool, account.id).await {
+        Ok(Some(policy)) => policy,
+        Ok(None) => PasswordOptions::default(),
+        Err(err) => {
+            println!("Failed to read generation policy: {}", err);
+            return Ok(());
+        }
+    };
+    print_generation_policy(&policy);
+
+    if confirm("Change the generation policy for this account?")? {
+        policy = prompt_generation_policy(&policy)?;
+        if let Err(err) = set_generation_policy(pool, account.id, Some(&policy)).await {
+            println!("Failed to save generation policy: {}", err);
+            return Ok(());
+        }
+        println!("Generation policy saved.");
+    }
+
+    let mut password = match generate_password(&policy) {
+        Ok(password) => password,
+        Err(err) => {
+            println!("Failed to generate password: {}", err);
+            return Ok(());
+        }
+    };
+    println!("Generated password: {}", password);
+    println!("{}", describe_password(&password));
+
+    if confirm(&format!("Save it as the new password for {}?", account.name))? {
+        // Encrypt password before updating
+        let master = obtain_master_credentials(pool).await?;
+        account.password = encrypt_password(&master.password, &password);
+
+        match update_account(pool, &account).await {
+            Ok(_) => println!("Account with ID {} was updated successfully.", account.id),
+            Err(e) => println!("Failed to update account with ID {}: {:?}", account.id, e),
+        }
+    }
+    password.zeroize();
+    Ok(())
+}
+
+fn print_generation_policy(policy: &PasswordOptions) {
+    let classes: Vec<&str> = [
+        (policy.lowercase, "lowercase"),
+        (policy.uppercase, "uppercase"),
+        (policy.digits, "digits"),
+        (policy.symbols, "symbols"),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, name)| *name)
+    .collect();
+
+    println!("Generation policy:");
+    println!("Length: {}", policy.length);
+    println!("Characters: {}", classes.join(", "));
+    if policy.excluded.is_empty() {
+        println!("Excluded: N/A");
+    } else {
+        println!("Excluded: {}", policy.excluded);
+    }
+    if policy.exclude_ambiguous {
+        println!("Look-alike characters ({}) left out", AMBIGUOUS);
+    }
+}
+
+/// Helper function for handle_generate_account_password()
+fn prompt_generation_policy(current: &PasswordOptions) -> io::Result<PasswordOptions> {
+    let (length, allow_short) = loop {
+        println!("Enter the password length (leave empty to keep {}):", current.length);
+        let input = get_user_input()?;
+        let length = if input.is_empty() {
+            current.length
+        } else {
+            match input.parse::<usize>() {
+                Ok(length) if length > 0 => length,
+                _ => {
+                    println!("Length must be a positive number.");
+                    continue;
+                }
+            }
+        };
+
+        // Only for sites that cap the length, the choice is kept with the policy
+        if length >= GENERATED_MIN_LENGTH {
+            break (length, false);
+        }
+        println!("Warning: passwords shorter than {} characters are easy to guess.", GENERATED_MIN_LENGTH);
+        if confirm("Use this length anyway?")? {
+            break (length, true);
+        }
+    };
+
+    let lowercase = confirm("Use lowercase letters?")?;
+    let uppercase = confirm("Use uppercase letters?")?;
+    let digits = confirm("Use digits?")?;
+    let symbols = confirm("Use symbols?")?;
+
+    let exclude_ambiguous = confirm(&format!("Leave out look-alike characters ({})?", AMBIGUOUS))?;
+
+    println!("Enter characters the site doesn't allow (leave empty for none):");
+    let excluded = get_user_input()?;
+
+    Ok(PasswordOptions { length, lowercase, uppercase, digits, symbols, excluded, exclude_ambiguous, allow_short })
+}
+
+/// Shows the first [`LOGIN_PREVIEW_COUNT`] accounts after logging in
+async fn print_login_preview(pool: &SqlitePool) {
+    if LOGIN_PREVIEW_COUNT <= 0 {
+        return;
+    }
+
+    match list_accounts_paged(pool, LOGIN_PREVIEW_COUNT, 0).await {
+        Ok(accounts) if accounts.is_empty() => println!("No accounts stored yet."),
+        Ok(accounts) => {
+            println!("First {} account(s) in the vault:", accounts.len());
+            for account in accounts {
+                println!("{}. {}", account.id, account.name);
+            }
+        }
+        Err(err) => println!("Failed to list accounts: {}", err),
+    }
+}
+
+/// Lists accounts that are due for review, shown after logging in
+async fn print_review_reminders(pool: &SqlitePool) {
+    match list_expired(pool).await {
+        Ok(expired) if expired.is_empty() => {}
+        Ok(expired) => {
+            println!("==============================");
+            println!("Accounts due for review:");
+            for (account, date, change_password_url) in expired {
+                println!("{}. {} (review by {})", account.id, account.name, date);
+                if let Some(change_password_url) = change_password_url {
+                    println!("   Change the password at: {}", change_password_url);
+                }
+            }
+        }
+        Err(err) => println!("Failed to check review dates: {}", err),
+    }
+}
+
+/// Sets the date an account should be reviewed or rotated by
+async fn handle_set_review_date(pool: &SqlitePool) -> io::Result<()> {
+    let Some(account) = select_account(pool, "Select an account to set a review date for").await? else {
+        return Ok(());
+    };
+
+    match get_expiry(pool, account.id).await {
+        Ok(Some(date)) => println!("Current review date: {}", date),
+        Ok(None) => println!("Current review date: N/A"),
+        Err(err) => {
+            println!("Failed to read review date: {}", err);
+            return Ok(());
+        }
+    }
+
+    let date = loop {
+        println!("Enter the review date as YYYY-MM-DD (\"none\" to clear, leave empty to keep current):");
+        let input = get_user_input()?;
+        if input.is_empty() {
+            return Ok(());
+        }
+        if input.eq_ignore_ascii_case("none") {
+            break None;
+        }
+        match NaiveDate::parse_from_str(&input, "%Y-%m-%d") {
+            Ok(date) => break Some(date),
+            Err(_) => println!("Invalid date: {}", input),
+        }
+    };
+
+    match set_expiry(pool, account.id, date).await {
+        Ok(_) => match date {
+            Some(date) => println!("{} will be due for review on {}.", account.name, date),
+            None => println!("Review date cleared for {}.", account.name),
+        },
+        Err(err) => println!("Failed to set review date: {}", err),
+    }
+    Ok(())
+}
+
+/// Shows how far a leaked username or email would reach, no passwords are decrypted
+async fn handle_username_usage(pool: &SqlitePool) -> io::Result<()> {
+    match username_usage_report(pool).await {
+        Ok(usage) if usage.is_empty() => println!("No username or email is used on more than one account."),
+        Ok(usage) => {
+            println!("Usernames and emails used on several accounts: ");
+            for (username, count) in usage {
+                println!("{} is used on {} accounts", username, count);
             }
-            _ => println!("Invalid option, please try again."),
         }
+        Err(err) => println!("Failed to count usernames: {}", err),
     }
+    Ok(())
 }
 
-fn get_user_input() -> String {
-    io::stdout().flush().unwrap();
-    
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
-    input.trim().to_string()
+/// Compacts the vault file after deletions, see [`vacuum_db()`]
+async fn handle_vacuum(pool: &SqlitePool) -> io::Result<()> {
+    println!("Compacting the database...");
+    match vacuum_db(pool).await {
+        Ok(report) => println!(
+            "Done, reclaimed {} KiB ({} KiB -> {} KiB).",
+            report.reclaimed() / 1024,
+            report.size_before / 1024,
+            report.size_after / 1024
+        ),
+        Err(err) => println!("Failed to compact the database: {:#}", err),
+    }
+    Ok(())
 }
 
-fn get_password() -> String {
-    if DEBUG_FLAG {
-        get_user_input()
+/// Toggles whether audits skip an account, for entries that aren't real passwords (API keys, shared throwaway logins)
+async fn handle_toggle_audit_exclusion(pool: &SqlitePool) -> io::Result<()> {
+    let Some(account) = select_account(pool, "Select an account").await? else {
+        return Ok(());
+    };
+
+    let excluded = match is_excluded_from_audit(pool, account.id).await {
+        Ok(excluded) => excluded,
+        Err(err) => {
+            println!("Failed to read audit setting: {}", err);
+            return Ok(());
+        }
+    };
+
+    let prompt = if excluded {
+        format!("{} is excluded from audits. Include it again?", account.name)
     } else {
-        io::stdout().flush().unwrap();
-        rpassword::read_password().unwrap()
+        format!("Exclude {} from audits? Weak, reused and overdue warnings won't be shown for it.", account.name)
+    };
+    if !confirm(&prompt)? {
+        return Ok(());
+    }
+
+    match set_audit_exclusion(pool, account.id, !excluded).await {
+        Ok(_) if excluded => println!("{} is included in audits again.", account.name),
+        Ok(_) => println!("{} is excluded from audits.", account.name),
+        Err(err) => println!("Failed to update audit setting: {}", err),
     }
+    Ok(())
 }
 
-async fn handle_add_account(pool: &SqlitePool) {
-    println!("Enter account name (ie. Google, X, Discord): ");
-    let name = get_user_input();
+/// Lists an account's attachments and lets the user add, extract or delete one
+async fn handle_attachments(pool: &SqlitePool) -> io::Result<()> {
+    let Some(account) = select_account(pool, "Select an account to manage attachments for").await? else {
+        return Ok(());
+    };
 
-    println!("(Optional) Enter url for account (ie. google.com, x.com, login.live.com): ");
-    let url_input = get_user_input();
-    // If the user enters an empty string, set url to None
-    let url = if url_input.is_empty() { None } else { Some(url_input) };
+    match list_attachments(pool, account.id).await {
+        Ok(attachments) if attachments.is_empty() => println!("No attachments for {}.", account.name),
+        Ok(attachments) => {
+            println!("Attachments for {}:", account.name);
+            for attachment in attachments {
+                println!("{}. {} ({} bytes)", attachment.id, attachment.filename, attachment.size);
+            }
+        }
+        Err(err) => {
+            println!("Failed to list attachments: {}", err);
+            return Ok(());
+        }
+    }
+    print_separator();
 
-    println!("Enter username: ");
-    let username = get_user_input();
-    
-    println!("Enter password: ");
-    let password = get_password();
+    println!("a. Add an attachment");
+    println!("e. Extract an attachment");
+    println!("d. Delete an attachment");
+    println!("Anything else returns to the main menu");
+    print!("Please choose an option: ");
+    match get_user_input()?.as_str() {
+        "a" => add_attachment_to_account(pool, &account).await,
+        "e" => extract_attachment(pool, &account).await,
+        "d" => delete_attachment(pool, &account).await,
+        _ => Ok(()),
+    }
+}
 
-    println!("(Optional) Enter description for account: ");
-    let description_input = get_user_input();
-    // If the user enters an empty string, set description to None
-    let description = if description_input.is_empty() { None } else { Some(description_input) };
+/// Helper function for handle_attachments()
+async fn add_attachment_to_account(pool: &SqlitePool, account: &Account) -> io::Result<()> {
+    println!("Enter the path of the file to attach:");
+    let path_input = get_user_input()?;
+    let path = Path::new(&path_input);
 
-    // Encrypt password before adding
-    let master = obtain_master_credentials(pool).await;
-    let encrypted_password = encrypt_password(&master.password, &password);
+    let Some(filename) = path.file_name().map(|name| name.to_string_lossy().to_string()) else {
+        println!("Not a file: {}", path.display());
+        return Ok(());
+    };
 
-    let account = Account::new(name, username, encrypted_password, url, description);
+    // Check the size before reading the whole file into memory
+    match fs::metadata(path) {
+        Ok(metadata) if !metadata.is_file() => {
+            println!("Not a file: {}", path.display());
+            return Ok(());
+        }
+        Ok(metadata) if metadata.len() > MAX_ATTACHMENT_SIZE as u64 => {
+            println!("File is {} bytes, attachments are limited to {} bytes.", metadata.len(), MAX_ATTACHMENT_SIZE);
+            return Ok(());
+        }
+        Ok(_) => {}
+        Err(err) => {
+            println!("Failed to read {}: {}", path.display(), err);
+            return Ok(());
+        }
+    }
 
-    match add_account(pool, &account).await {
-        Ok(_result) => { ()
-        },
+    let mut contents = match fs::read(path) {
+        Ok(contents) => contents,
         Err(err) => {
-            println!("Failed to list accounts: {}", err);
+            println!("Failed to read {}: {}", path.display(), err);
+            return Ok(());
         }
+    };
+    let size = contents.len() as i64;
+
+    // Encrypt file before adding
+    let master = obtain_master_credentials(pool).await?;
+    let attachment = Attachment::new(account.id, filename, encrypt_bytes(&master.password, &contents));
+    contents.zeroize();
+
+    match add_attachment(pool, &attachment, size).await {
+        Ok(_) => println!("Attached {} to {}.", attachment.filename, account.name),
+        Err(err) => println!("Failed to add attachment: {}", err),
     }
+    Ok(())
 }
 
-fn print_account_summary_details(account: &AccountSummary) {
-    println!("Account ID: {}", account.id);
-    println!("Name: {}", account.name);
-    match &account.description {
-        Some(desc) => println!("Description: {}", desc),
-        None => println!("Description: N/A"),
+/// Helper function for handle_attachments()
+/// 
+/// The decrypted file is written to a new file only the user can read, an existing file is never overwritten
+async fn extract_attachment(pool: &SqlitePool, account: &Account) -> io::Result<()> {
+    println!("Enter the attachment ID to extract:");
+    let Some(attachment) = find_account_attachment(pool, account).await? else {
+        return Ok(());
+    };
+
+    println!("Enter the path to save {} to:", attachment.filename);
+    let destination = get_user_input()?;
+
+    let master = reauthenticate(pool).await?;
+    let mut contents = match decrypt_bytes(&master.password, &attachment.data) {
+        Ok(contents) => contents,
+        Err(err) => {
+            println!("Failed to decrypt attachment: {}", err);
+            return Ok(());
+        }
+    };
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let result = options.open(&destination).and_then(|mut file| file.write_all(&contents));
+    contents.zeroize();
+
+    match result {
+        Ok(_) => println!("Saved {} to {}.", attachment.filename, destination),
+        Err(err) => println!("Failed to write {}: {}", destination, err),
     }
+    Ok(())
 }
 
-fn print_account_details(account: &Account, master_password: &String) {
-    println!("Account Details:");
-    println!("ID: {}", account.id);
-    println!("Name: {}", account.name);
-    println!("Username: {}", account.username);
+/// Helper function for handle_attachments()
+async fn delete_attachment(pool: &SqlitePool, account: &Account) -> io::Result<()> {
+    println!("Enter the attachment ID to delete:");
+    let Some(attachment) = find_account_attachment(pool, account).await? else {
+        return Ok(());
+    };
 
-    // Decrypt password before showing
-    let decrypted_password = decrypt_password(master_password, &account.password);
-    println!("Password: {}", decrypted_password);
-    match &account.url {
-        Some(url) => println!("URL: {}", url),
-        None => println!("URL: N/A"),
+    if !confirm(&format!("Delete {}?", attachment.filename))? {
+        return Ok(());
     }
-    match &account.description {
-        Some(description) => println!("Description: {}", description),
-        None => println!("Description: N/A"),
+    if require_reauth_for(SensitiveAction::DeleteAttachment) {
+        reauthenticate(pool).await?;
+    }
+
+    match delete_attachment_by_id(pool, attachment.id).await {
+        Ok(_) => println!("Attachment {} deleted.", attachment.filename),
+        Err(err) => println!("Failed to delete attachment: {}", err),
     }
+    Ok(())
 }
 
-async fn handle_list_accounts(pool: &SqlitePool) {
-    println!("Listing accounts: ");
+/// Reads an attachment ID and fetches it, only if it belongs to the given account
+async fn find_account_attachment(pool: &SqlitePool, account: &Account) -> io::Result<Option<Attachment>> {
+    let input = get_user_input()?;
+    let Ok(id) = input.parse::<i64>() else {
+        println!("Invalid attachment ID: {}", input);
+        return Ok(None);
+    };
 
-    match list_accounts(pool).await {
-        Ok(results) => {
-            for account in results {
-                print_account_summary_details(&account);
-                print_separator();
-            }
-        },
-        Err(err) => {
-            println!("Failed to list accounts: {}", err);
+    match get_attachment_by_id(pool, id).await {
+        Ok(attachment) if attachment.account_id == account.id => Ok(Some(attachment)),
+        _ => {
+            println!("No attachment with ID {} for {}.", id, account.name);
+            Ok(None)
         }
     }
 }
 
-async fn handle_get_account(pool: &SqlitePool) {
-    println!("Enter account ID or name:");
-    let user_input = get_user_input();
+/// Return type for [`obtain_master_credentials()`]
+pub struct MasterCredentials {
+    pub username: String,
+    pub password: String,
+}
 
-    // Automatically determine if id or name
-    if let Ok(id) = user_input.parse::<i64>() {
-        match get_account_by_id(pool, id).await {
-            Ok(account) => {
-                let master = obtain_master_credentials(pool).await;
-                print_account_details(&account, &master.password);
-            },
-            Err(err) => {
-                println!("Error fetching account by ID: {}", err);
-            }
-        }
-    } else {
-        match get_account_by_name(pool, &user_input).await {
-            Ok(account) => {
-                let master = obtain_master_credentials(pool).await;
-                print_account_details(&account, &master.password);
-            },
-            Err(err) => {
-                println!("Error fetching account by name: {}", err);
-            }
-        }
+impl Drop for MasterCredentials {
+    fn drop(&mut self) {
+        self.username.zeroize();
+        self.password.zeroize();
     }
 }
+/// Takes user input
+///
+/// Returns [`MasterCredentials`] with username and password,
+/// or the input error if reading from stdin failed
+pub async fn obtain_master_credentials(pool: &SqlitePool) -> io::Result<MasterCredentials> {
+    login(pool, true).await
+}
 
-async fn handle_delete_account(pool: &SqlitePool) {
-    println!("Enter account ID or name:");
-    let user_input = get_user_input();
+/// Like [`obtain_master_credentials()`] but the password is always typed, the OS keyring is skipped
+///
+/// Used to confirm sensitive actions, which a stored password would otherwise pass silently
+pub async fn reauthenticate(pool: &SqlitePool) -> io::Result<MasterCredentials> {
+    login(pool, false).await
+}
 
-    // Automatically determine if id or name
-    if let Ok(id) = user_input.parse::<i64>() {
-        match delete_account_by_id(pool, id).await {
-            Ok(account) => {
-                account
-            },
-            Err(err) => {
-                println!("Error fetching account by ID: {}", err);
+async fn login(pool: &SqlitePool, keyring_allowed: bool) -> io::Result<MasterCredentials> {
+    let mut attempts = 3;
+    let mut keyring_tried = !keyring_allowed;
+
+    // Prompts go to stderr so the `get` subcommand's stdout stays clean for piping
+
+    loop {
+        let username = if SINGLE_MASTER_FLAG {
+            // The single master may have been renamed, so look it up instead of assuming "default"
+            match get_first_master(pool).await {
+                Ok(master) => master.username.clone(),
+                Err(_) => "default".to_string(),
             }
-        }
-    } else {
-        match delete_account_by_name(pool, &user_input).await {
-            Ok(account) => {
-                account
-            },
-            Err(err) => {
-                println!("Error fetching account by name: {}", err);
+        } else {
+            eprint!("{}", t("login.username_prompt"));
+            get_user_input()?
+        };
+
+        // A stored password is only tried once, if it is out of date the user is prompted
+        let from_keyring = match KEYRING_VAULT.get() {
+            Some(vault) if !keyring_tried => {
+                keyring_tried = true;
+                let stored = fetch_master_from_keyring(vault, &username).unwrap_or_else(|err| {
+                    eprintln!("{}", t_with("login.keyring_read_failed", &[("error", &err)]));
+                    None
+                });
+                debug_log!("OS keyring entry for {}: {}", username, if stored.is_some() { "found" } else { "none" });
+                stored
+            }
+            _ => None,
+        };
+        let used_keyring = from_keyring.is_some();
+
+        let password = match from_keyring {
+            Some(password) => password,
+            None => {
+                eprint!("{}", t("login.password_prompt"));
+                match get_master_password() {
+                    Ok(password) => password,
+                    Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                        eprintln!();
+                        eprintln!("{}", t("login.timed_out"));
+                        pool.close().await;
+                        release_vault_lock();
+                        process::exit(1);
+                    }
+                    Err(err) => return Err(err),
+                }
             }
+        };
+        // Masters enrolled with a key file are hashed and derive keys from both secrets
+        let master = get_master_by_username(pool, &username).await.ok();
+        let mut keyfile = None;
+        if master.as_ref().is_some_and(|master| master.keyfile_required) {
+            let Some(loaded) = KEY_FILE.get() else {
+                eprintln!("{}", t("login.keyfile_required"));
+                pool.close().await;
+                release_vault_lock();
+                process::exit(1);
+            };
+            keyfile = Some(loaded);
         }
-    }
-}
 
-async fn handle_update_account(pool: &SqlitePool) {
-    println!("Enter the account ID or name to update:");
+        let verified = verify_typed_master(pool, &username, &password, keyfile).await;
+        // The keyring only ever holds the password itself, not the key file
+        let mut plain_password = password;
 
-    let input = get_user_input();
-    
-    match input.parse::<i64>() {
-        Ok(id) => {
-            match get_account_by_id(pool, id).await {
-                Ok(mut account) => {
-                    update_account_details(pool, &mut account);
+        match verified {
+            Ok(Some(password)) => {
+                if keyring_allowed && !used_keyring {
+                    offer_keyring_storage(&username, &plain_password)?;
                 }
-                Err(_) => {
-                    println!("No account found with ID: {}", id);
+                plain_password.zeroize();
+                if let Some(master) = &master {
+                    let _ = SESSION_MASTER.set(master.id);
                 }
+                eprintln!("{}", t("login.logging_in"));
+                return Ok(MasterCredentials { username, password });
+            },
+            // Not the user's fault, so it doesn't count as an attempt
+            Err(err) if NO_LOGIN_RETRY.load(Ordering::Relaxed) => {
+                plain_password.zeroize();
+                eprintln!("{}", t_with("login.database_error", &[("error", &err)]));
+                pool.close().await;
+                release_vault_lock();
+                process::exit(1);
             }
-        }
-        Err(_) => {
-            let name = input.trim().to_string();
-            match get_account_by_name(pool, &name).await {
-                Ok(mut account) => {
-                    update_account_details(pool, &mut account);
+            Err(err) => {
+                plain_password.zeroize();
+                eprintln!("{}", t_with("login.database_error_retry", &[("error", &err)]));
+            }
+            Ok(None) if used_keyring => {
+                plain_password.zeroize();
+                eprintln!("{}", t("login.keyring_outdated"));
+                forget_keyring_master(&username);
+            }
+            Ok(None) if NO_LOGIN_RETRY.load(Ordering::Relaxed) => {
+                plain_password.zeroize();
+                eprintln!("{}", t("login.invalid"));
+                pool.close().await;
+                release_vault_lock();
+                process::exit(1);
+            }
+            Ok(None) => {
+                let hint = login_input_hint(&plain_password);
+                plain_password.zeroize();
+                attempts -= 1;
+                if attempts <= 0 {
+                    eprintln!("{}", t("login.max_attempts"));
+                    pool.close().await;
+                    release_vault_lock();
+                    process::exit(1);
                 }
-                Err(_) => {
-                    println!("No account found with name: {}", name);
+                eprintln!("{}", t_with("login.invalid_retry", &[("attempts", &attempts)]));
+                if let Some(hint) = hint {
+                    eprintln!("{}", t_with("login.hint", &[("hint", &hint)]));
                 }
             }
         }
     }
 }
 
-/// Helper function for handle_update_account()
-async fn update_account_details(pool: &SqlitePool, account: &mut Account) {
-    println!("\nCurrent account details:");
-    println!("Name: {}", account.name);
-    println!("Username: {}", account.username);
-    if let Some(url) = &account.url {
-        println!("URL: {}", url);
+/// Asks for the master password of a second vault, ie. the one `diff` compares against
+/// 
+/// Unlike [`obtain_master_credentials()`] the OS keyring is never used and the session stays logged in
+/// to the first vault. The --keyfile is applied if the other vault's master needs one, as backups do
+pub async fn obtain_other_master_password(pool: &SqlitePool, vault: &Path) -> anyhow::Result<String> {
+    let username = if SINGLE_MASTER_FLAG {
+        get_first_master(pool).await?.username.clone()
     } else {
-        println!("URL: N/A");
+        eprint!("Enter master username for {}: ", vault.display());
+        get_user_input()?
+    };
+    let master = get_master_by_username(pool, &username).await?;
+    if master.keyfile_required && KEY_FILE.get().is_none() {
+        anyhow::bail!("{} needs a key file, run again with --keyfile <path>", vault.display());
     }
-    if let Some(description) = &account.description {
-        println!("Description: {}", description);
-    } else {
-        println!("Description: N/A");
+
+    for attempts_left in (0..3).rev() {
+        eprint!("Enter master password for {}: ", vault.display());
+        let mut password = get_master_password()?;
+        let keyfile = KEY_FILE.get().filter(|_| master.keyfile_required);
+        let verified = verify_typed_master(pool, &username, &password, keyfile).await;
+        password.zeroize();
+        if let Some(password) = verified? {
+            return Ok(password);
+        }
+        if attempts_left > 0 {
+            eprintln!("Invalid credentials. Please try again. {} attempts left", attempts_left);
+        }
     }
+    anyhow::bail!("Maximum attempts reached for {}", vault.display())
+}
 
-    // Step 3: Ask for new values
-    println!("\nEnter the new account name (leave empty to keep current):");
-    let name = get_user_input();
-    let name = if name.is_empty() { account.name.clone() } else { name };
+/// Checks a typed master password in each form from [`master_password_candidates()`]
+/// 
+/// # Returns
+/// 
+/// Returns the secret that matched, combined with the key file if one is given, or None if no form did
+async fn verify_typed_master(
+    pool: &SqlitePool,
+    username: &String,
+    password: &str,
+    keyfile: Option<&KeyFileDigest>,
+) -> anyhow::Result<Option<String>> {
+    let mut candidates = master_password_candidates(password);
+    if let Some(keyfile) = keyfile {
+        for candidate in candidates.iter_mut() {
+            let combined = combine_with_keyfile(candidate, keyfile);
+            candidate.zeroize();
+            *candidate = combined;
+        }
+    }
 
-    println!("Enter the new username (leave empty to keep current):");
-    let username = get_user_input();
-    let username = if username.is_empty() { account.username.clone() } else { username };
+    let mut verified = Ok(None);
+    for candidate in candidates.iter_mut() {
+        if matches!(verified, Ok(None)) {
+            verified = verify_master(pool, username, candidate)
+                .await
+                .map(|matched| matched.then(|| std::mem::take(candidate)));
+        }
+        candidate.zeroize();
+    }
+    verified
+}
 
-    println!("Enter the new password (leave empty to keep current):");
-    let password = get_password();
-    let password = if password.is_empty() { account.password.clone() } else { password };
+/// Spots common typing mistakes in a rejected master password, since the input is hidden
+/// 
+/// Only looks at what was typed, never at the stored hash
+fn login_input_hint(password: &str) -> Option<&'static str> {
+    if password.trim() != password {
+        return Some(t("login.hint_spaces"));
+    }
+    // Caps Lock also inverts shifted letters, so "Secret" comes out as "sECRET"
+    let uppercase = password.chars().filter(|c| c.is_uppercase()).count();
+    let lowercase = password.chars().filter(|c| c.is_lowercase()).count();
+    if uppercase > lowercase * 2 {
+        return Some(t("login.hint_caps_lock"));
+    }
+    None
+}
 
-    println!("Enter the new URL (leave empty to keep current):");
-    let url = get_user_input();
-    let url = if url.is_empty() { account.url.clone() } else { Some(url) };
+/// After a typed login with --use-keyring, asks once per run whether to store the master password
+fn offer_keyring_storage(username: &str, password: &str) -> io::Result<()> {
+    let Some(vault) = KEYRING_VAULT.get() else {
+        return Ok(());
+    };
+    if KEYRING_OFFERED.swap(true, Ordering::Relaxed) {
+        return Ok(());
+    }
 
-    println!("Enter the new description (leave empty to keep current):");
-    let description = get_user_input();
-    let description = if description.is_empty() { account.description.clone() } else { Some(description) };
+    eprintln!("Storing the master password in the OS keyring skips this prompt on future launches,");
+    eprintln!("but anyone who can use your OS account will be able to open the vault.");
+    eprint!("Store it in the keyring? [y/N]: ");
+    let answer = get_user_input()?;
+    if matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+        match store_master_in_keyring(vault, username, password) {
+            Ok(()) => eprintln!("Master password stored in the OS keyring."),
+            Err(err) => eprintln!("Failed to store the master password in the OS keyring: {}", err),
+        }
+    }
+    Ok(())
+}
 
-    // Encrypt password before adding
-    let master = obtain_master_credentials(pool).await;
-    let encrypted_password = encrypt_password(&master.password, &password);
+/// Removes a master's stored password, used when it is changed or renamed
+fn forget_keyring_master(username: &str) {
+    if let Some(vault) = KEYRING_VAULT.get() {
+        if let Err(err) = delete_master_from_keyring(vault, username) {
+            eprintln!("Failed to remove the master password from the OS keyring: {}", err);
+        }
+    }
+}
 
-    let updated_account = Account {
-        id: account.id, // Keep the same ID
-        name: name,
-        username: username,
-        password: encrypted_password,
-        url: url,
-        description: description,
+/// Creates the first master account, enforcing the master password policy
+/// 
+/// Returns whether the master account was stored
+async fn handle_master_setup(pool: &SqlitePool) -> io::Result<bool> {
+    let username = if SINGLE_MASTER_FLAG {
+        "default".to_string()
+    } else {
+        loop {
+            print!("Choose a master username: ");
+            let username = get_user_input()?;
+            if !username.is_empty() {
+                break username;
+            }
+            println!("Username can't be empty.");
+        }
     };
 
-    match update_account(pool, &updated_account).await {
+    let policy = MasterPasswordPolicy::default();
+    let mut password = loop {
+        print!("Choose a master password: ");
+        let mut password = get_password()?;
+        if let Err(err) = validate_master_strength(&password, &policy) {
+            println!("{}. Please try again.", err);
+            password.zeroize();
+            continue;
+        }
+
+        print!("Confirm master password: ");
+        let mut confirmation = get_password()?;
+        let mut normalized = normalize_master_password(&password);
+        let matches = normalized == normalize_master_password(&confirmation);
+        confirmation.zeroize();
+        password.zeroize();
+        if matches {
+            break normalized;
+        }
+        println!("Passwords do not match. Please try again.");
+        normalized.zeroize();
+    };
+
+    // A key file given at setup is required from then on
+    let keyfile = KEY_FILE.get();
+    let mut secret = match keyfile {
+        Some(keyfile) => combine_with_keyfile(&password, keyfile),
+        None => password.clone(),
+    };
+    password.zeroize();
+
+    let mut master = Master::new(username, hash_master_password(&secret).expect("Error hashing password"));
+    master.keyfile_required = keyfile.is_some();
+    secret.zeroize();
+
+    if master.keyfile_required {
+        println!("The key file will be required to unlock this vault, keep a backup of it.");
+    }
+
+    match add_master(pool, &master).await {
         Ok(_) => {
-            println!("Account with ID {} was updated successfully.", updated_account.id);
+            println!("Master account created.");
+            Ok(true)
         }
-        Err(e) => {
-            println!("Failed to update account with ID {}: {:?}", updated_account.id, e);
+        Err(err) => {
+            println!("Failed to create master account: {}", err);
+            Ok(false)
         }
     }
 }
 
-/// Return type for [`obtain_master_credentials()`]
-struct MasterCredentials {
-    username: String,
-    password: String,
-}
+/// Changes the master username, also available in single master mode
+async fn handle_rename_master(pool: &SqlitePool) -> io::Result<()> {
+    println!("Login with master account to rename:");
 
-impl Drop for MasterCredentials {
-    fn drop(&mut self) {
-        self.username.zeroize();
-        self.password.zeroize();
+    let master_creds = obtain_master_credentials(pool).await?;
+
+    let mut master = match get_master_by_username(pool, &master_creds.username).await {
+        Ok(master) => master,
+        Err(_) => {
+            println!("No master found with that username: {}", master_creds.username);
+            return Ok(());
+        }
+    };
+
+    println!("Current username: {}", master.username);
+    println!("Enter the new username (leave empty to keep current):");
+    let new_username = get_user_input()?;
+    if new_username.is_empty() || new_username == master.username {
+        println!("Username unchanged.");
+        return Ok(());
     }
-}
-/// Takes user input
-/// 
-/// Returns [`MasterCredentials`] with username and password
-async fn obtain_master_credentials(pool: &SqlitePool) -> MasterCredentials {
-    let mut attempts = 3;
 
-    loop {
-        let username = if SINGLE_MASTER_FLAG {
-            "default".to_string()
-        } else {
-            print!("Enter master username: ");
-            get_user_input()
-        };
+    // Usernames identify masters at login, so they have to stay unique
+    if get_master_by_username(pool, &new_username).await.is_ok() {
+        println!("A master named {} already exists.", new_username);
+        return Ok(());
+    }
 
-        print!("Enter master password: ");
-        let password = get_password();
+    master.username = new_username;
+    match update_master(pool, &master).await {
+        Ok(_) => {
+            println!("Master account renamed to {}.", master.username);
+            forget_keyring_master(&master_creds.username);
+        }
+        Err(e) => println!("Failed to rename master account: {:?}", e),
+    }
+    Ok(())
+}
 
-        match verify_master(pool, &username, &password).await {
-            Ok(true) => {
-                println!("Logging in...");
-                return MasterCredentials { username, password };
-            },
-            Ok(false) | Err(_) => {
-                attempts -= 1;
-                if attempts <= 0 {
-                    println!("Max attempts reached. Exiting...");
-                    process::exit(1);
-                }
-                println!("Invalid credentials. Please try again. {} attempts remaining", attempts);
-            }
+/// Confirms re-encrypting the vault for a new master password and backs it up first
+/// 
+/// The backup sits next to the database file and opens with the old master password
+/// 
+/// # Returns
+/// 
+/// Returns the backup path, Some(None) for an --ephemeral vault which has no file to back up,
+/// or None if the user declined or the backup failed
+async fn backup_before_master_change(pool: &SqlitePool) -> Option<Option<PathBuf>> {
+    match confirm("Every stored password will be re-encrypted with the new master password. Continue?") {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(err) => {
+            report_input_error(&err);
+            return None;
+        }
+    }
+
+    let backup_path = match db_file_path(pool).await {
+        Ok(db_path) => {
+            let mut backup_path = db_path.into_os_string();
+            backup_path.push(format!(".{}.bak", Local::now().format("%Y%m%d-%H%M%S")));
+            PathBuf::from(backup_path)
         }
+        // A failed change rolls back anyway, and --save-to is what keeps an in-memory vault
+        Err(_) => return Some(None),
+    };
+    if let Err(err) = backup_vault(pool, &backup_path).await {
+        println!("Failed to back up the vault: {:#}", err);
+        return None;
     }
+
+    println!("Backed up the vault to {}", backup_path.display());
+    Some(Some(backup_path))
 }
 
-async fn handle_change_master_password(pool: &SqlitePool) {
+async fn handle_change_master_password(pool: &SqlitePool) -> io::Result<()> {
     println!("Login with master account to update:");
 
-    let master_creds = obtain_master_credentials(pool).await;
+    let master_creds = reauthenticate(pool).await?;
 
     match get_master_by_username(pool, &master_creds.username).await {
         Ok(master) => {
@@ -353,36 +1925,134 @@ async fn handle_change_master_password(pool: &SqlitePool) {
                 master.username.clone()
             } else {
                 println!("Enter the new username (leave empty to keep current):");
-                let input_username = get_user_input();
+                let input_username = get_user_input()?;
                 if input_username.is_empty() { master.username.clone() } else { input_username }
             };
 
-            println!("Enter the new password (leave empty to keep current):");
-            let password = get_password();
-            let password = if password.is_empty() {
-                master.password.clone() 
-            } else {
+            let policy = MasterPasswordPolicy::default();
+            let mut new_password = loop {
+                println!("Enter the new password (leave empty to keep current):");
+                let mut password = get_password()?;
+                if password.is_empty() {
+                    break None;
+                }
+
+                // Reject weak master passwords before hashing
+                match validate_master_strength(&password, &policy) {
+                    Ok(()) => {
+                        let normalized = normalize_master_password(&password);
+                        password.zeroize();
+                        break Some(normalized);
+                    }
+                    Err(err) => println!("{}. Please try again.", err),
+                }
+            };
+            // Keys are derived from the password combined with the key file, if the master requires one
+            let mut new_secret = new_password.as_ref().map(|new_password| {
+                if master.keyfile_required {
+                    // Logging in already required the key file, so it is loaded
+                    let keyfile = KEY_FILE.get().expect("Key file missing after login");
+                    combine_with_keyfile(new_password, keyfile)
+                } else {
+                    new_password.clone()
+                }
+            });
+
+            let password = match &new_secret {
                 // Hash password before adding
-                hash_master_password(&password).expect("Error hashing password")
+                Some(new_secret) => hash_master_password(new_secret).expect("Error hashing password"),
+                None => master.password.clone(),
             };
 
             let updated_master = Master {
                 id: master.id,
-                username: username,
-                password: password
+                username,
+                password,
+                keyfile_required: master.keyfile_required
+            };
+
+            // Stored passwords are encrypted with the master password, so they follow the change
+            let mut backup_path = None;
+            let result = match &new_secret {
+                Some(new_secret) => {
+                    match backup_before_master_change(pool).await {
+                        Some(path) => {
+                            backup_path = path;
+                            println!("Re-encrypting stored passwords...");
+                            Some(change_master_password(pool, &updated_master, &master_creds.password, new_secret).await)
+                        }
+                        None => None,
+                    }
+                }
+                None => Some(update_master(pool, &updated_master).await.map(|_| 0)),
             };
 
-            match update_master(pool, &updated_master).await {
-                Ok(_) => {
+            match result {
+                None => println!("Master password was not changed."),
+                Some(Ok(skipped)) => {
                     println!("Account with ID {} was updated successfully.", updated_master.id);
+                    if skipped > 0 {
+                        println!("{} entries were encrypted by another master and were left unchanged.", skipped);
+                    }
+                    if new_secret.is_some() || updated_master.username != master.username {
+                        forget_keyring_master(&master.username);
+                    }
+
+                    // The database file is keyed by the master password, so it has to follow the change
+                    #[cfg(feature = "sqlcipher")]
+                    if let Some(new_password) = &new_password {
+                        if let Err(e) = rekey_db(pool, new_password).await {
+                            println!("Failed to re-key the database file: {}", e);
+                            if let Some(backup_path) = &backup_path {
+                                println!("The vault as it was before the change is backed up at {}", backup_path.display());
+                            }
+                        }
+                    }
                 }
-                Err(e) => {
+                Some(Err(e)) => {
                     println!("Failed to update account with ID {}: {:?}", updated_master.id, e);
+                    // The re-encryption runs in a single transaction, a failure leaves every entry as it was
+                    if let Some(backup_path) = &backup_path {
+                        println!("No entries were changed. The backup from before the change is kept at {}", backup_path.display());
+                    }
                 }
             }
+
+            if let Some(new_password) = new_password.as_mut() {
+                new_password.zeroize();
+            }
+            if let Some(new_secret) = new_secret.as_mut() {
+                new_secret.zeroize();
+            }
         }
         Err(_) => {
             println!("No master found with that username: {}", master_creds.username);
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(input: &str) -> String {
+        let mut input = input.to_string();
+        strip_line_ending(&mut input);
+        input
+    }
+
+    #[test]
+    fn line_ending_is_stripped_but_spaces_are_kept() {
+        assert_eq!(stripped("  spaced  \n"), "  spaced  ");
+        assert_eq!(stripped("  spaced  \r\n"), "  spaced  ");
+        assert_eq!(stripped("  spaced  "), "  spaced  ");
+    }
+
+    #[test]
+    fn only_one_line_ending_is_stripped() {
+        assert_eq!(stripped("password\n\n"), "password\n");
+        assert_eq!(stripped("password\r\n\r\n"), "password\r\n");
+        assert_eq!(stripped("password\r"), "password\r");
+    }
 }
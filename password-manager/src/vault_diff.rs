@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+
+use sqlx::sqlite::SqlitePool;
+use zeroize::Zeroize;
+
+use crate::{database::{get_account_by_id, list_accounts, Account}, encryption::try_decrypt_password};
+
+/// Differences found by [`diff_vaults()`], accounts are matched by name
+#[derive(Debug, Default)]
+pub struct VaultDiff {
+    /// Accounts only in the first vault
+    pub only_in_a: Vec<String>,
+    /// Accounts only in the second vault
+    pub only_in_b: Vec<String>,
+    pub changed: Vec<ChangedAccount>,
+    /// Accounts with the same contents in both
+    pub unchanged: usize,
+}
+
+impl VaultDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// An account in both vaults whose contents differ
+#[derive(Debug)]
+pub struct ChangedAccount {
+    pub name: String,
+    /// Fields that differ, ie. "username" or "password"
+    pub fields: Vec<&'static str>,
+}
+
+/// Compares two vaults, each decrypted with its own master password
+///
+/// Passwords, sensitive notes and login steps are decrypted to compare them but never part of the result,
+/// only the name of the field that differs is. A password that can't be decrypted in either vault is
+/// reported as "password (unreadable)"
+pub async fn diff_vaults(
+    pool_a: &SqlitePool,
+    pool_b: &SqlitePool,
+    master_a: &String,
+    master_b: &String,
+) -> anyhow::Result<VaultDiff> {
+    let accounts_a = accounts_by_name(pool_a).await?;
+    let mut accounts_b = accounts_by_name(pool_b).await?;
+    let mut diff = VaultDiff::default();
+
+    for (name, account_a) in accounts_a {
+        let Some(account_b) = accounts_b.remove(&name) else {
+            diff.only_in_a.push(name);
+            continue;
+        };
+
+        let fields = changed_fields(&account_a, &account_b, master_a, master_b);
+        if fields.is_empty() {
+            diff.unchanged += 1;
+        } else {
+            diff.changed.push(ChangedAccount { name, fields });
+        }
+    }
+    diff.only_in_b = accounts_b.into_keys().collect();
+
+    Ok(diff)
+}
+
+/// Every account of a vault, sorted by name so the diff comes out in a stable order
+async fn accounts_by_name(pool: &SqlitePool) -> anyhow::Result<BTreeMap<String, Account>> {
+    let mut accounts = BTreeMap::new();
+    for summary in list_accounts(pool).await? {
+        let account = get_account_by_id(pool, summary.id).await?;
+        accounts.insert(account.name.clone(), account);
+    }
+    Ok(accounts)
+}
+
+fn changed_fields(a: &Account, b: &Account, master_a: &String, master_b: &String) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if a.username != b.username {
+        fields.push("username");
+    }
+    if a.url != b.url {
+        fields.push("url");
+    }
+    if a.change_password_url != b.change_password_url {
+        fields.push("change password url");
+    }
+    if a.category != b.category {
+        fields.push("category");
+    }
+
+    match secrets_equal(Some(&a.password), true, Some(&b.password), true, master_a, master_b) {
+        Some(true) => {}
+        Some(false) => fields.push("password"),
+        None => fields.push("password (unreadable)"),
+    }
+    let description = secrets_equal(a.description.as_ref(), a.encrypted_note, b.description.as_ref(), b.encrypted_note, master_a, master_b);
+    if description != Some(true) {
+        fields.push("description");
+    }
+    let login_steps = secrets_equal(a.login_steps.as_ref(), a.encrypted_login_steps, b.login_steps.as_ref(), b.encrypted_login_steps, master_a, master_b);
+    if login_steps != Some(true) {
+        fields.push("login steps");
+    }
+
+    fields
+}
+
+/// Compares two optionally encrypted values by their plaintext, None if either can't be decrypted
+fn secrets_equal(
+    a: Option<&String>,
+    a_encrypted: bool,
+    b: Option<&String>,
+    b_encrypted: bool,
+    master_a: &String,
+    master_b: &String,
+) -> Option<bool> {
+    let (Some(a), Some(b)) = (a, b) else {
+        return Some(a.is_none() && b.is_none());
+    };
+
+    let reveal = |value: &String, encrypted: bool, master: &String| {
+        if encrypted { try_decrypt_password(master, value).ok() } else { Some(value.clone()) }
+    };
+    let mut a = reveal(a, a_encrypted, master_a);
+    let mut b = reveal(b, b_encrypted, master_b);
+    let equal = match (&a, &b) {
+        (Some(a), Some(b)) => Some(a == b),
+        _ => None,
+    };
+
+    a.zeroize();
+    b.zeroize();
+    equal
+}